@@ -0,0 +1,72 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+// Source of the trade_utils path dependency, which owns Kline and Trade
+const TRADE_UTILS_SRC: &str = "../trade_utils/src";
+
+// Files of the directory and its subdirectories, a missing or unreadable directory has none
+fn source_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) => {
+            println!("cargo:warning=Skip {} in the code version, {}", dir.display(), err);
+            return;
+        }
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            source_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// MOMENTUM_CODE_VERSION changes with the package version, src, Cargo.lock, the trade_utils
+/// source and the enabled features, it keys the hypertune cache.
+fn main() {
+    let mut files = Vec::new();
+    source_files(Path::new("src"), &mut files);
+    for path in ["Cargo.lock", TRADE_UTILS_SRC] {
+        let path = Path::new(path);
+        if path.is_dir() {
+            source_files(path, &mut files);
+        } else if path.exists() {
+            files.push(path.to_owned());
+        }
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+    // Sorted with / separators so the hash is the same on every os
+    let mut files: Vec<(String, PathBuf)> = files
+        .into_iter()
+        .map(|file| (file.to_string_lossy().replace('\\', "/"), file))
+        .collect();
+    files.sort();
+    let mut hasher = Sha256::new();
+    for (name, file) in files {
+        hasher.update(name.as_bytes());
+        match fs::read(&file) {
+            Ok(content) => hasher.update(content),
+            Err(err) => println!("cargo:warning=Skip {} in the code version, {}", name, err),
+        }
+    }
+    let mut features: Vec<String> = env::vars()
+        .map(|(key, _)| key)
+        .filter(|key| key.starts_with("CARGO_FEATURE_"))
+        .collect();
+    features.sort();
+    hasher.update(features.join(",").as_bytes());
+    println!(
+        "cargo:rustc-env=MOMENTUM_CODE_VERSION={}-{}",
+        env!("CARGO_PKG_VERSION"),
+        &hex::encode(hasher.finalize())[..16]
+    );
+    println!("cargo:rerun-if-changed=src");
+}
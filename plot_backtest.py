@@ -3,12 +3,13 @@ import plotly.graph_objects as go
 import os
 from plotly.subplots import make_subplots
 
-output_dir = 'C:\\rust_code\\momentum\\backtest_output\\'
+# backtest_output next to this script, on any os
+output_dir = os.path.join(os.path.dirname(os.path.abspath(__file__)), 'backtest_output')
 filenames = os.listdir(output_dir)
 fig = make_subplots()
 
 for filename in filenames:
-    df = pd.read_csv(f'{output_dir}{filename}')
+    df = pd.read_csv(os.path.join(output_dir, filename))
 
     fig.add_trace(
         go.Scatter(
@@ -0,0 +1,346 @@
+use std::path::{Path, PathBuf};
+
+use momentum::{
+    backtest::{run_backtest, BacktestResult},
+    config::{validated, ConfigOverrides},
+    hypertune::run_hypertune,
+    types::BacktestConfig,
+};
+use pyo3::{
+    exceptions::PyValueError,
+    prelude::*,
+    types::{IntoPyDict, PyDict},
+};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use trade_utils::types::kline::Kline;
+
+// Source of the configs built from python values in the error messages
+const PYTHON_SOURCE: &str = "<python>";
+const KLINE_TIMESTAMP_COLUMNS: [&str; 2] = ["open_timestamp", "close_timestamp"];
+const KLINE_PRICE_COLUMNS: [&str; 5] = ["open", "high", "low", "close", "volume"];
+const TRADE_COLUMNS: [&str; 9] = [
+    "entry_ts",
+    "exit_ts",
+    "entry_side",
+    "entry_price",
+    "exit_price",
+    "position",
+    "profit",
+    "fee",
+    "partial",
+];
+const EQUITY_COLUMNS: [&str; 3] = ["timestamp", "usd_balance", "equity"];
+const SUMMARY_COLUMNS: [&str; 13] = [
+    "initial_captial",
+    "usd_balance",
+    "max_usd",
+    "min_usd",
+    "win",
+    "lose",
+    "win_rate",
+    "total_fee",
+    "total_profit",
+    "partial_exits",
+    "max_drawdown",
+    "max_drawdown_ratio",
+    "sharpe_ratio",
+];
+
+fn value_error(err: impl ToString) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+/// Python value to json through the json module, so only plain python values are accepted.
+fn to_value(obj: &Bound<'_, PyAny>) -> PyResult<Value> {
+    let json: String = obj
+        .py()
+        .import_bound("json")?
+        .call_method1("dumps", (obj,))?
+        .extract()?;
+    serde_json::from_str(&json).map_err(value_error)
+}
+
+fn to_py(py: Python<'_>, value: &impl Serialize) -> PyResult<PyObject> {
+    let json = serde_json::to_string(value).map_err(value_error)?;
+    Ok(py
+        .import_bound("json")?
+        .call_method1("loads", (json,))?
+        .unbind())
+}
+
+/// DataFrame of the records with the columns in the given order.
+fn data_frame(py: Python<'_>, records: &impl Serialize, columns: &[&str]) -> PyResult<PyObject> {
+    let records = to_py(py, records)?;
+    let kwargs = [("columns", columns.to_vec())].into_py_dict_bound(py);
+    Ok(py
+        .import_bound("pandas")?
+        .call_method("DataFrame", (records,), Some(&kwargs))?
+        .unbind())
+}
+
+fn column<'py, T: FromPyObject<'py>>(
+    frame: &Bound<'py, PyAny>,
+    name: &str,
+    dtype: &str,
+) -> PyResult<Vec<T>> {
+    let values = frame
+        .get_item(name)
+        .map_err(|_| value_error(format!("klines have no column `{}`", name)))?;
+    frame
+        .py()
+        .import_bound("numpy")?
+        .call_method1("asarray", (values, dtype))?
+        .call_method0("tolist")?
+        .extract()
+}
+
+/// Klines from a DataFrame or a dict of numpy arrays, timestamps are in milliseconds.
+fn klines_from_frame(frame: &Bound<'_, PyAny>) -> PyResult<Vec<Kline>> {
+    let mut rows: Vec<Map<String, Value>> = Vec::new();
+    for name in KLINE_TIMESTAMP_COLUMNS {
+        let values: Vec<i64> = column(frame, name, "int64")?;
+        rows.resize_with(values.len(), Map::new);
+        for (row, value) in rows.iter_mut().zip(values) {
+            row.insert(name.to_owned(), json!(value));
+        }
+    }
+    for name in KLINE_PRICE_COLUMNS {
+        let values: Vec<f64> = column(frame, name, "float64")?;
+        if values.len() != rows.len() {
+            return Err(value_error(format!(
+                "kline column `{}` has {} rows, expected {}",
+                name,
+                values.len(),
+                rows.len()
+            )));
+        }
+        for (row, value) in rows.iter_mut().zip(values) {
+            row.insert(name.to_owned(), json!(value));
+        }
+    }
+    rows.into_iter()
+        .map(|row| serde_json::from_value(Value::Object(row)).map_err(value_error))
+        .collect()
+}
+
+fn optional_klines(frame: Option<&Bound<'_, PyAny>>) -> PyResult<Vec<Kline>> {
+    match frame {
+        Some(frame) if !frame.is_none() => klines_from_frame(frame),
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[pyclass(name = "BacktestConfig", module = "momentum_py")]
+#[derive(Clone)]
+struct PyBacktestConfig {
+    config: BacktestConfig,
+}
+
+impl PyBacktestConfig {
+    fn from_value(value: Value) -> PyResult<PyBacktestConfig> {
+        let config = validated(Path::new(PYTHON_SOURCE), value).map_err(value_error)?;
+        Ok(PyBacktestConfig { config })
+    }
+}
+
+#[pymethods]
+impl PyBacktestConfig {
+    /// `BacktestConfig(initial_captial=10000.0, ...)` with the fields of the json config.
+    #[new]
+    #[pyo3(signature = (**fields))]
+    fn new(fields: Option<&Bound<'_, PyDict>>) -> PyResult<PyBacktestConfig> {
+        match fields {
+            Some(fields) => PyBacktestConfig::from_value(to_value(fields.as_any())?),
+            None => PyBacktestConfig::from_value(json!({})),
+        }
+    }
+
+    /// Loads a json, toml or yaml config like the cli, with its profile and `key=value` overrides.
+    #[staticmethod]
+    #[pyo3(signature = (path, profile = None, overrides = Vec::new()))]
+    fn load(
+        path: PathBuf,
+        profile: Option<String>,
+        overrides: Vec<String>,
+    ) -> PyResult<PyBacktestConfig> {
+        let config_overrides = ConfigOverrides::new(profile, &overrides).map_err(value_error)?;
+        let config = config_overrides.load_config(&path).map_err(value_error)?;
+        Ok(PyBacktestConfig { config })
+    }
+
+    /// Copy of the config with the given fields replaced.
+    #[pyo3(signature = (**fields))]
+    fn replace(&self, fields: Option<&Bound<'_, PyDict>>) -> PyResult<PyBacktestConfig> {
+        let mut value = serde_json::to_value(&self.config).map_err(value_error)?;
+        if let Some(fields) = fields {
+            if let Value::Object(fields) = to_value(fields.as_any())? {
+                for (field, field_value) in fields {
+                    value[field] = field_value;
+                }
+            }
+        }
+        PyBacktestConfig::from_value(value)
+    }
+
+    fn to_dict(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_py(py, &self.config)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self.config)
+    }
+}
+
+#[pyclass(name = "BacktestResult", module = "momentum_py")]
+struct PyBacktestResult {
+    result: BacktestResult,
+}
+
+#[pymethods]
+impl PyBacktestResult {
+    #[getter]
+    fn symbol(&self) -> String {
+        self.result.symbol.clone()
+    }
+
+    /// Summary metrics as a dict, with the `long` and `short` breakdowns.
+    #[getter]
+    fn metrics(&self, py: Python<'_>) -> PyResult<PyObject> {
+        to_py(py, &self.result.metrics)
+    }
+
+    /// One row per exit, a trade closed by the take-profit ladder has a row per level.
+    #[getter]
+    fn trades(&self, py: Python<'_>) -> PyResult<PyObject> {
+        data_frame(py, &self.result.trades, &TRADE_COLUMNS)
+    }
+
+    /// Balance and mark-to-market equity at every kline close.
+    #[getter]
+    fn equity_curve(&self, py: Python<'_>) -> PyResult<PyObject> {
+        data_frame(py, &self.result.equity_curve, &EQUITY_COLUMNS)
+    }
+
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.result).map_err(value_error)
+    }
+
+    fn __repr__(&self) -> String {
+        let metrics = &self.result.metrics;
+        format!(
+            "BacktestResult(symbol={:?}, usd_balance={:.4}, win={}, lose={}, max_drawdown={:.4})",
+            self.result.symbol,
+            metrics.usd_balance,
+            metrics.win,
+            metrics.lose,
+            metrics.max_drawdown
+        )
+    }
+}
+
+#[pyclass(name = "Backtest", module = "momentum_py")]
+struct PyBacktest {
+    config: BacktestConfig,
+    symbol: String,
+}
+
+#[pymethods]
+impl PyBacktest {
+    #[new]
+    #[pyo3(signature = (config, symbol = "BTCUSDT".to_string()))]
+    fn new(config: PyBacktestConfig, symbol: String) -> PyBacktest {
+        PyBacktest {
+            config: config.config,
+            symbol,
+        }
+    }
+
+    /// Backtests the klines, each run starts over from the config. `regime_klines` are the
+    /// klines of `regime_collection` for the regime filter.
+    #[pyo3(signature = (klines, regime_klines = None))]
+    fn run(
+        &self,
+        py: Python<'_>,
+        klines: &Bound<'_, PyAny>,
+        regime_klines: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<PyBacktestResult> {
+        let klines = klines_from_frame(klines)?;
+        let regime_klines = optional_klines(regime_klines)?;
+        let result =
+            py.allow_threads(|| run_backtest(&self.config, &klines, regime_klines, &self.symbol));
+        Ok(PyBacktestResult { result })
+    }
+}
+
+/// Backtests every config of the hypertune config dict, one row per config with the tuned
+/// fields and the summary metrics.
+#[pyfunction]
+#[pyo3(signature = (hypertune_config, klines, regime_klines = None, symbol = "BTCUSDT".to_string()))]
+fn hypertune(
+    py: Python<'_>,
+    hypertune_config: &Bound<'_, PyAny>,
+    klines: &Bound<'_, PyAny>,
+    regime_klines: Option<&Bound<'_, PyAny>>,
+    symbol: String,
+) -> PyResult<PyObject> {
+    let value = to_value(hypertune_config)?;
+    if !value.is_object() {
+        return Err(value_error("hypertune_config should be a dict"));
+    }
+    let klines = klines_from_frame(klines)?;
+    let regime_klines = optional_klines(regime_klines)?;
+    let (results, tune_fields) = py
+        .allow_threads(|| run_hypertune(&value, &klines, &regime_klines, &symbol))
+        .map_err(|invalid| {
+            value_error(format!(
+                "hypertune_config field `{}` {}",
+                invalid.field, invalid.reason
+            ))
+        })?;
+
+    let mut columns: Vec<String> = tune_fields.clone();
+    columns.extend(SUMMARY_COLUMNS.iter().map(|column| column.to_string()));
+    for side in ["long", "short"] {
+        for field in ["win_rate", "total_profit", "max_drawdown"] {
+            columns.push(format!("{}_{}", side, field));
+        }
+    }
+    let mut rows = Vec::new();
+    for result in results.iter() {
+        let config_value = serde_json::to_value(&result.config).map_err(value_error)?;
+        let mut row = match serde_json::to_value(&result.metrics).map_err(value_error)? {
+            Value::Object(row) => row,
+            _ => Map::new(),
+        };
+        for field in tune_fields.iter() {
+            row.insert(field.clone(), config_value[field].clone());
+        }
+        for (side, side_metric) in [
+            ("long", &result.metrics.long),
+            ("short", &result.metrics.short),
+        ] {
+            row.insert(format!("{}_win_rate", side), json!(side_metric.win_rate()));
+            row.insert(
+                format!("{}_total_profit", side),
+                json!(side_metric.total_profit),
+            );
+            row.insert(
+                format!("{}_max_drawdown", side),
+                json!(side_metric.max_drawdown),
+            );
+        }
+        rows.push(row);
+    }
+    let columns: Vec<&str> = columns.iter().map(|column| column.as_str()).collect();
+    data_frame(py, &rows, &columns)
+}
+
+#[pymodule]
+fn momentum_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyBacktestConfig>()?;
+    m.add_class::<PyBacktest>()?;
+    m.add_class::<PyBacktestResult>()?;
+    m.add_function(wrap_pyfunction!(hypertune, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,431 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    path::Path,
+};
+
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+
+use crate::{
+    backtest::BacktestSummary,
+    config::{validated, ConfigError, InvalidField},
+    hypertune::config_object,
+    types::BacktestConfig,
+};
+
+/// Metric the hypertune configs are ranked by, higher is better.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Objective {
+    TotalProfit,
+    UsdBalance,
+    WinRate,
+    #[default]
+    ReturnOverDrawdown, // total_profit / max_drawdown
+    MaxDrawdown, // Ranked by the smallest drawdown
+}
+
+// Drawdown floor of return_over_drawdown, in usd, so runs without a loss don't divide by zero
+const MIN_DRAWDOWN: f64 = 1.;
+
+impl Objective {
+    /// Objective of a row of hypertune_output.csv, an error names a missing or non-numeric column.
+    pub fn value(&self, row: &HashMap<String, String>) -> Result<f64, InvalidField> {
+        let number = |name: &str| -> Result<f64, InvalidField> {
+            let cell = column(row, name)?;
+            cell.parse::<f64>().map_err(|_| InvalidField {
+                field: name.to_owned(),
+                reason: format!("should be a number, got `{}`", cell),
+            })
+        };
+        let value = match self {
+            Objective::TotalProfit => number("total_profit")?,
+            Objective::UsdBalance => number("usd_balance")?,
+            Objective::WinRate => number("win_rate")?,
+            Objective::ReturnOverDrawdown => {
+                number("total_profit")? / number("max_drawdown")?.max(MIN_DRAWDOWN)
+            }
+            Objective::MaxDrawdown => -number("max_drawdown")?,
+        };
+        // win_rate of a run without trades
+        if value.is_nan() {
+            Ok(0.)
+        } else {
+            Ok(value)
+        }
+    }
+}
+
+// Cell of the column, e.g. max_drawdown is missing from outputs older than the column
+fn column<'a>(row: &'a HashMap<String, String>, name: &str) -> Result<&'a String, InvalidField> {
+    row.get(name).ok_or_else(|| InvalidField {
+        field: name.to_owned(),
+        reason: "is not a column of the hypertune output".to_owned(),
+    })
+}
+
+/// Objective of a config over the symbols of a basket hypertune, higher is better.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq)]
+pub enum BasketObjective {
+    #[default]
+    MedianSharpe,
+    WorstSharpe,
+    WorstDrawdown,           // Ranked by the smallest max_drawdown_ratio of any symbol
+    WorstReturnOverDrawdown, // Of the symbol with the lowest total_profit / max_drawdown
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len() % 2 == 1 {
+        values[middle]
+    } else {
+        (values[middle - 1] + values[middle]) / 2.
+    }
+}
+
+impl BasketObjective {
+    /// Combined objective of the metrics of every symbol.
+    pub fn value(&self, metrics: &[BacktestSummary]) -> f64 {
+        let mut sharpe_ratios: Vec<f64> = metrics.iter().map(|m| m.sharpe_ratio).collect();
+        match self {
+            BasketObjective::MedianSharpe => median(&mut sharpe_ratios),
+            BasketObjective::WorstSharpe => sharpe_ratios.into_iter().fold(f64::MAX, f64::min),
+            BasketObjective::WorstDrawdown => -metrics
+                .iter()
+                .map(|m| m.max_drawdown_ratio)
+                .fold(0., f64::max),
+            BasketObjective::WorstReturnOverDrawdown => metrics
+                .iter()
+                .map(|m| m.total_profit / m.max_drawdown.max(MIN_DRAWDOWN))
+                .fold(f64::MAX, f64::min),
+        }
+    }
+}
+
+/// One row of hypertune_output.csv.
+#[derive(Debug, Clone)]
+pub struct HypertuneRun {
+    pub params: Vec<Value>, // Values of the tuned fields, in the order of `tune_fields`
+    pub columns: HashMap<String, String>,
+    pub objective: f64,
+    pub robustness: f64,
+}
+
+/// Objective of the runs over the values of two tuned fields, the best run of each cell.
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    pub x_field: String,
+    pub y_field: String,
+    pub x_values: Vec<Value>,
+    pub y_values: Vec<Value>,
+    pub cells: Vec<Vec<Option<f64>>>, // cells[y][x]
+}
+
+#[derive(Debug, Clone)]
+pub struct HypertuneAnalysis {
+    pub objective: Objective,
+    pub tune_fields: Vec<String>,
+    pub runs: Vec<HypertuneRun>,
+}
+
+pub fn read_hypertune_output(path: &Path) -> Result<Vec<HashMap<String, String>>, ConfigError> {
+    let file = File::open(path).map_err(|source| ConfigError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    csv::Reader::from_reader(file)
+        .deserialize()
+        .map(|row| {
+            row.map_err(|source| ConfigError::Syntax {
+                path: path.to_owned(),
+                source: Box::new(source),
+            })
+        })
+        .collect()
+}
+
+// Numbers in numeric order, anything else after them by its json
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+fn sorted_values(runs: &[HypertuneRun], index: usize) -> Vec<Value> {
+    let mut values: Vec<Value> = runs.iter().map(|run| run.params[index].clone()).collect();
+    values.sort_by(compare_values);
+    values.dedup();
+    values
+}
+
+fn position(values: &[Value], value: &Value) -> usize {
+    values.iter().position(|v| v == value).unwrap()
+}
+
+/// Mean of the objective over the run and its neighbours on the grid, one step away on one of
+/// the tuned fields, less its standard deviation. A peak surrounded by poor configs scores low
+/// and a plateau of good configs scores high.
+fn robustness_scores(runs: &[HypertuneRun], field_count: usize) -> Vec<f64> {
+    let grid: Vec<Vec<Value>> = (0..field_count)
+        .map(|index| sorted_values(runs, index))
+        .collect();
+    let coordinates: Vec<Vec<i64>> = runs
+        .iter()
+        .map(|run| {
+            run.params
+                .iter()
+                .enumerate()
+                .map(|(index, value)| position(&grid[index], value) as i64)
+                .collect()
+        })
+        .collect();
+    let run_at: HashMap<&Vec<i64>, usize> = coordinates
+        .iter()
+        .enumerate()
+        .map(|(run_index, coordinate)| (coordinate, run_index))
+        .collect();
+    coordinates
+        .iter()
+        .map(|coordinate| {
+            let mut objectives = Vec::new();
+            if let Some(run_index) = run_at.get(coordinate) {
+                objectives.push(runs[*run_index].objective);
+            }
+            // A step of -1 or 1 on one field, 2 * field_count neighbours at most
+            for field in 0..field_count {
+                for step in [-1, 1] {
+                    let mut neighbour = coordinate.clone();
+                    neighbour[field] += step;
+                    if let Some(run_index) = run_at.get(&neighbour) {
+                        objectives.push(runs[*run_index].objective);
+                    }
+                }
+            }
+            let mean = objectives.iter().sum::<f64>() / objectives.len() as f64;
+            let variance = objectives
+                .iter()
+                .map(|objective| (objective - mean).powi(2))
+                .sum::<f64>()
+                / objectives.len() as f64;
+            mean - variance.sqrt()
+        })
+        .collect()
+}
+
+/// Runs of the rows of hypertune_output.csv, `tune_fields` in the order of `tune_params`. An
+/// error names a column the rows lack.
+pub fn analyze(
+    tune_fields: Vec<String>,
+    rows: Vec<HashMap<String, String>>,
+    objective: Objective,
+) -> Result<HypertuneAnalysis, InvalidField> {
+    let mut runs: Vec<HypertuneRun> = rows
+        .into_iter()
+        .map(|columns| {
+            // Tuned fields are written as json
+            let params = tune_fields
+                .iter()
+                .map(|field| {
+                    let cell = column(&columns, field)?;
+                    Ok(serde_json::from_str(cell).unwrap_or(Value::String(cell.clone())))
+                })
+                .collect::<Result<_, InvalidField>>()?;
+            Ok(HypertuneRun {
+                params,
+                objective: objective.value(&columns)?,
+                columns,
+                robustness: 0.,
+            })
+        })
+        .collect::<Result<_, InvalidField>>()?;
+    let scores = robustness_scores(&runs, tune_fields.len());
+    for (run, score) in runs.iter_mut().zip(scores) {
+        run.robustness = score;
+    }
+    Ok(HypertuneAnalysis {
+        objective,
+        tune_fields,
+        runs,
+    })
+}
+
+impl HypertuneAnalysis {
+    /// Runs from the best objective.
+    pub fn ranked(&self) -> Vec<&HypertuneRun> {
+        let mut runs: Vec<&HypertuneRun> = self.runs.iter().collect();
+        runs.sort_by(|a, b| b.objective.total_cmp(&a.objective));
+        runs
+    }
+
+    /// Runs from the best robustness score.
+    pub fn robust_ranked(&self) -> Vec<&HypertuneRun> {
+        let mut runs: Vec<&HypertuneRun> = self.runs.iter().collect();
+        runs.sort_by(|a, b| b.robustness.total_cmp(&a.robustness));
+        runs
+    }
+
+    /// The most robust run, the best objective breaks ties.
+    pub fn recommended(&self) -> Option<&HypertuneRun> {
+        self.runs.iter().max_by(|a, b| {
+            a.robustness
+                .total_cmp(&b.robustness)
+                .then(a.objective.total_cmp(&b.objective))
+        })
+    }
+
+    /// Heatmaps of every pair of tuned fields with more than one value.
+    pub fn heatmaps(&self) -> Vec<Heatmap> {
+        let fields: Vec<usize> = (0..self.tune_fields.len())
+            .filter(|index| sorted_values(&self.runs, *index).len() > 1)
+            .collect();
+        let mut heatmaps = Vec::new();
+        for (i, x_index) in fields.iter().enumerate() {
+            for y_index in fields[i + 1..].iter() {
+                heatmaps.push(self.heatmap(*x_index, *y_index));
+            }
+        }
+        heatmaps
+    }
+
+    fn heatmap(&self, x_index: usize, y_index: usize) -> Heatmap {
+        let x_values = sorted_values(&self.runs, x_index);
+        let y_values = sorted_values(&self.runs, y_index);
+        let mut cells = vec![vec![None; x_values.len()]; y_values.len()];
+        for run in self.runs.iter() {
+            let x = position(&x_values, &run.params[x_index]);
+            let y = position(&y_values, &run.params[y_index]);
+            let cell: &mut Option<f64> = &mut cells[y][x];
+            *cell = Some(cell.map_or(run.objective, |best| best.max(run.objective)));
+        }
+        Heatmap {
+            x_field: self.tune_fields[x_index].clone(),
+            y_field: self.tune_fields[y_index].clone(),
+            x_values,
+            y_values,
+            cells,
+        }
+    }
+
+    /// Backtest config of the run, the fixed fields of the hypertune config with its tuned values.
+    pub fn run_config(
+        &self,
+        path: &Path,
+        hypertune_value: &Value,
+        run: &HypertuneRun,
+    ) -> Result<BacktestConfig, ConfigError> {
+        let mut config_value: Map<String, Value> = config_object(hypertune_value)
+            .map_err(|invalid| ConfigError::Invalid {
+                path: path.to_owned(),
+                field: invalid.field,
+                reason: invalid.reason,
+            })?
+            .iter()
+            .filter(|(_, value)| !value.is_object())
+            .map(|(field, value)| (field.clone(), value.clone()))
+            .collect();
+        for (field, value) in self.tune_fields.iter().zip(run.params.iter()) {
+            config_value.insert(field.clone(), value.clone());
+        }
+        validated(path, Value::Object(config_value))
+    }
+
+    /// Tuned values of the run for the logs and the report.
+    pub fn describe(&self, run: &HypertuneRun) -> BTreeMap<String, String> {
+        self.tune_fields
+            .iter()
+            .zip(run.params.iter())
+            .map(|(field, value)| (field.clone(), value.to_string()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn run(params: Vec<Value>, objective: f64) -> HypertuneRun {
+        HypertuneRun {
+            params,
+            columns: HashMap::new(),
+            objective,
+            robustness: 0.,
+        }
+    }
+
+    fn assert_near(value: f64, expected: f64) {
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn robustness_is_mean_less_std_of_the_neighbours() {
+        let runs: Vec<HypertuneRun> = [0., 30., 0.]
+            .iter()
+            .enumerate()
+            .map(|(index, objective)| run(vec![json!(index)], *objective))
+            .collect();
+        let scores = robustness_scores(&runs, 1);
+        // [0, 30] at the ends, [0, 30, 0] in the middle
+        assert_near(scores[0], 15. - 15.);
+        assert_near(scores[1], 10. - 200f64.sqrt());
+        assert_near(scores[2], 0.);
+    }
+
+    #[test]
+    fn robustness_prefers_a_plateau_to_a_peak() {
+        let runs: Vec<HypertuneRun> = [0., 50., 0., 20., 20., 20.]
+            .iter()
+            .enumerate()
+            .map(|(index, objective)| run(vec![json!(index)], *objective))
+            .collect();
+        let scores = robustness_scores(&runs, 1);
+        assert_near(scores[4], 20.);
+        assert!(scores[4] > scores[1]);
+    }
+
+    #[test]
+    fn robustness_neighbours_are_one_field_away() {
+        // (0, 0) neighbours (0, 1) and (1, 0), not the diagonal (1, 1)
+        let runs = vec![
+            run(vec![json!(1), json!("a")], 4.),
+            run(vec![json!(1), json!("b")], 0.),
+            run(vec![json!(2), json!("a")], 0.),
+            run(vec![json!(2), json!("b")], 100.),
+        ];
+        let scores = robustness_scores(&runs, 2);
+        assert_near(scores[0], 4. / 3. - (32f64 / 9.).sqrt());
+    }
+
+    #[test]
+    fn robustness_steps_over_the_sorted_values() {
+        // Rows in any order, 10 is next to 8 and 12 numerically
+        let runs = vec![
+            run(vec![json!(12)], 6.),
+            run(vec![json!(8)], 6.),
+            run(vec![json!(10)], 6.),
+            run(vec![json!(100)], 0.),
+        ];
+        let scores = robustness_scores(&runs, 1);
+        assert_near(scores[2], 6.);
+        assert_near(scores[0], 4. - 8f64.sqrt());
+    }
+
+    #[test]
+    fn run_config_rejects_a_config_that_is_not_an_object() {
+        let analysis = analyze(Vec::new(), Vec::new(), Objective::TotalProfit).unwrap();
+        let error = analysis
+            .run_config(
+                Path::new("hypertune.json"),
+                &json!([1]),
+                &run(Vec::new(), 0.),
+            )
+            .unwrap_err();
+        assert!(matches!(error, ConfigError::Invalid { .. }));
+    }
+}
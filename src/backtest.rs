@@ -1,17 +1,25 @@
-use chrono::NaiveDateTime;
+use chrono::DateTime;
 use log::*;
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::fs::{File, OpenOptions};
 use std::path::Path;
 use trade_utils::types::kline::Kline;
-use trade_utils::types::trade::Trade;
+use trade_utils::types::trade::{Trade, TradeSide};
 
-use crate::strategy::{open_trade, sl_tp_exit};
-use crate::types::BacktestConfig;
+use crate::execution::LiveFill;
+use crate::filter::EntryFilters;
+use crate::indicators::{Atr, Indicator};
+use crate::signal::MomentumSignal;
+use crate::strategy::{open_trade, sl_tp_exit, ExitOutput};
+use crate::types::{BacktestConfig, ManagedTrade};
 
 pub struct Backtest {
     config: BacktestConfig,
-    momentum: VecDeque<f64>,
+    signal: MomentumSignal,
+    filters: EntryFilters,
+    regime_klines: Vec<Kline>,
+    atr: Atr,
     output_result: bool,
 }
 
@@ -27,6 +35,145 @@ pub struct BacktestMetric {
     pub min_usd: f64,
     pub fee: f64,
     pub profit: f64,
+    pub partial_exits: usize,
+    pub long: SideMetric,
+    pub short: SideMetric,
+    pub trades: Vec<TradeRecord>,
+    pub equity_curve: Vec<EquityPoint>,
+    pub simulate_fills: bool, // Apply fill_timing and slippage, only the backtest sets it
+    pub next_kline: Option<Kline>, // Orders decided on the current close fill in it
+    pub live_fills: Vec<LiveFill>, // Limit entries filled on the exchange, for the journal
+    pub journal_fills: VecDeque<LiveFill>, // Journaled limit entries replay applies in order
+}
+
+/// One exit of a trade, a trade closed by the take-profit ladder has a record per level.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeRecord {
+    pub entry_ts: i64,
+    pub exit_ts: i64,
+    pub entry_side: TradeSide,
+    pub entry_price: f64,
+    pub exit_price: f64,
+    pub position: f64, // Closed position
+    pub profit: f64,
+    pub fee: f64, // Exit fee
+    #[serde(default)]
+    pub entry_fee: f64, // Entry fee of the closed position
+    pub partial: bool,
+}
+
+/// Balance and mark-to-market equity at a kline close.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EquityPoint {
+    pub timestamp: i64,
+    pub usd_balance: f64,
+    pub equity: f64, // usd_balance plus the unrealized profit of the open trades
+}
+
+/// Breakdown of the trades of one side.
+#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+pub struct SideMetric {
+    pub win: usize,
+    pub lose: usize,
+    pub total_fee: f64,
+    pub total_profit: f64,
+    pub peak_profit: f64,
+    pub max_drawdown: f64, // Max drop of total_profit from its peak, in usd
+}
+
+impl SideMetric {
+    pub fn win_rate(&self) -> f64 {
+        self.win as f64 / (self.win + self.lose) as f64
+    }
+
+    pub fn record(&mut self, profit: f64, fee: f64) {
+        self.total_profit += profit;
+        self.total_fee += fee;
+        self.peak_profit = self.peak_profit.max(self.total_profit);
+        self.max_drawdown = self.max_drawdown.max(self.peak_profit - self.total_profit);
+    }
+}
+
+/// Metrics of a finished backtest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestSummary {
+    pub initial_captial: f64,
+    pub usd_balance: f64,
+    pub max_usd: f64,
+    pub min_usd: f64,
+    pub win: usize,
+    pub lose: usize,
+    pub win_rate: f64,
+    pub total_fee: f64,
+    pub total_profit: f64,
+    pub partial_exits: usize,
+    pub max_drawdown: f64,       // Max drop of the equity from its peak, in usd
+    pub max_drawdown_ratio: f64, // Same drop relative to the peak
+    pub sharpe_ratio: f64,       // Of the equity returns per kline, annualized
+    pub long: SideMetric,
+    pub short: SideMetric,
+}
+
+/// Everything a backtest produces, see `run_backtest`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestResult {
+    pub symbol: String,
+    pub metrics: BacktestSummary,
+    pub trades: Vec<TradeRecord>,
+    pub equity_curve: Vec<EquityPoint>,
+}
+
+/// Backtests `klines` without writing any output file, for embedding the engine.
+/// `regime_klines` may be empty when the config has no regime filter.
+pub fn run_backtest(
+    config: &BacktestConfig,
+    klines: &[Kline],
+    regime_klines: Vec<Kline>,
+    symbol: &str,
+) -> BacktestResult {
+    let mut backtest = Backtest::new(config, false);
+    backtest.set_regime_klines(regime_klines);
+    backtest.run(klines, symbol.to_string()).into_result(symbol)
+}
+
+const YEAR_MS: f64 = 365. * 86_400_000.;
+
+/// Mean over standard deviation of the equity returns between kline closes, annualized by the
+/// kline interval. 0 without returns or without any change of the equity.
+fn sharpe_ratio(equity_curve: &[EquityPoint]) -> f64 {
+    let (first, last) = match (equity_curve.first(), equity_curve.last()) {
+        (Some(first), Some(last)) if equity_curve.len() > 2 => (first, last),
+        _ => return 0.,
+    };
+    let returns: Vec<f64> = equity_curve
+        .windows(2)
+        .filter(|points| points[0].equity > 0.)
+        .map(|points| points[1].equity / points[0].equity - 1.)
+        .collect();
+    if returns.is_empty() {
+        return 0.;
+    }
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64;
+    let kline_ms = (last.timestamp - first.timestamp) as f64 / (equity_curve.len() - 1) as f64;
+    if variance <= 0. || kline_ms <= 0. {
+        return 0.;
+    }
+    mean / variance.sqrt() * (YEAR_MS / kline_ms).sqrt()
+}
+
+/// Profit of the open trades if they were closed at `price`.
+pub fn unrealized_profit(trades: &[ManagedTrade], price: f64) -> f64 {
+    trades
+        .iter()
+        .map(|trade| {
+            if trade.entry_side == TradeSide::Buy {
+                (price - trade.entry_price) * trade.position
+            } else {
+                (trade.entry_price - price) * trade.position
+            }
+        })
+        .sum()
 }
 
 impl BacktestMetric {
@@ -39,6 +186,84 @@ impl BacktestMetric {
             ..Default::default()
         }
     }
+
+    pub fn side_mut(&mut self, side: &TradeSide) -> &mut SideMetric {
+        if *side == TradeSide::Sell {
+            &mut self.short
+        } else {
+            &mut self.long
+        }
+    }
+
+    /// Records the balance and equity at the close of `kline`, after its exits and entries.
+    pub fn record_equity(&mut self, trades: &[ManagedTrade], kline: &Kline) {
+        let unrealized = unrealized_profit(trades, kline.close);
+        self.equity_curve.push(EquityPoint {
+            timestamp: kline.close_timestamp,
+            usd_balance: self.usd_balance,
+            equity: self.usd_balance + unrealized,
+        });
+    }
+
+    pub fn summary(&self) -> BacktestSummary {
+        let mut peak = self.initial_captial;
+        let mut max_drawdown = 0.;
+        let mut max_drawdown_ratio = 0.;
+        for point in &self.equity_curve {
+            peak = f64::max(peak, point.equity);
+            max_drawdown = f64::max(max_drawdown, peak - point.equity);
+            if peak > 0. {
+                max_drawdown_ratio = f64::max(max_drawdown_ratio, (peak - point.equity) / peak);
+            }
+        }
+        let closed = self.win + self.lose;
+        BacktestSummary {
+            initial_captial: self.initial_captial,
+            usd_balance: self.usd_balance,
+            max_usd: self.max_usd,
+            min_usd: self.min_usd,
+            win: self.win,
+            lose: self.lose,
+            win_rate: if closed > 0 {
+                self.win as f64 / closed as f64
+            } else {
+                0.
+            },
+            total_fee: self.total_fee,
+            total_profit: self.total_profit,
+            partial_exits: self.partial_exits,
+            max_drawdown,
+            max_drawdown_ratio,
+            sharpe_ratio: sharpe_ratio(&self.equity_curve),
+            long: self.long.clone(),
+            short: self.short.clone(),
+        }
+    }
+
+    pub fn into_result(self, symbol: &str) -> BacktestResult {
+        BacktestResult {
+            symbol: symbol.to_string(),
+            metrics: self.summary(),
+            trades: self.trades,
+            equity_curve: self.equity_curve,
+        }
+    }
+
+    pub fn log_sides(&self) {
+        for (name, side) in [("long", &self.long), ("short", &self.short)] {
+            info!(
+                "{} win: {}, lose: {}, win_rate: {:.4}, total_profit: {:.4}, total_fee: {:.4}, max_drawdown: {:.4} ({:.4} of initial_captial)",
+                name,
+                side.win,
+                side.lose,
+                side.win_rate(),
+                side.total_profit,
+                side.total_fee,
+                side.max_drawdown,
+                side.max_drawdown / self.initial_captial
+            );
+        }
+    }
 }
 
 impl Backtest {
@@ -51,7 +276,10 @@ impl Backtest {
     pub fn new(config: &BacktestConfig, output_result: bool) -> Backtest {
         let backtest = Backtest {
             config: config.clone(),
-            momentum: VecDeque::new(),
+            signal: MomentumSignal::new(config),
+            filters: EntryFilters::new(config),
+            regime_klines: Vec::new(),
+            atr: Atr::new(config.atr_period),
             output_result,
         };
         if output_result {
@@ -81,59 +309,72 @@ impl Backtest {
         backtest
     }
 
-    pub fn run(&mut self, klines: &Vec<Kline>, symbol: String) -> BacktestMetric {
+    /// Klines of `regime_collection` for the regime filter, over the same period as the backtest.
+    pub fn set_regime_klines(&mut self, regime_klines: Vec<Kline>) {
+        self.regime_klines = regime_klines;
+    }
+
+    pub fn run(&mut self, klines: &[Kline], symbol: String) -> BacktestMetric {
         let mut metric = BacktestMetric::new(&self.config);
-        let mut trades: Vec<Trade> = Vec::new();
+        metric.simulate_fills = true;
+        let mut trades: Vec<ManagedTrade> = Vec::new();
 
         let output_trade_log_name = self.output_name();
+        let mut regime_index = 0;
         for k_index in 0..klines.len() {
             let kline = &klines[k_index];
+            metric.next_kline = klines.get(k_index + 1).cloned();
+            while regime_index < self.regime_klines.len()
+                && self.regime_klines[regime_index].close_timestamp <= kline.close_timestamp
+            {
+                self.filters
+                    .update_regime(&self.regime_klines[regime_index]);
+                regime_index += 1;
+            }
+            let atr = self.atr.update(kline);
 
             sl_tp_exit(
                 &mut metric,
                 &mut self.config,
                 &mut trades,
-                self.output_result,
-                &output_trade_log_name,
                 &kline,
-                None,
+                atr,
+                ExitOutput {
+                    output_trade_log: self.output_result,
+                    output_trade_log_name: &output_trade_log_name,
+                    api_client_opt: None,
+                },
             );
 
-            let look_back = self.config.look_back_count as usize;
-            if k_index > look_back {
-                let prev_close = klines[k_index - look_back].close;
-                let curr_close = klines[k_index].close;
-                let momentum = curr_close - prev_close;
-                self.add_momentum(momentum);
-
-                if self.momentum.len() >= 2 {
-                    open_trade(
-                        symbol.clone(),
-                        &mut metric,
-                        &mut self.config,
-                        &mut trades,
-                        &self.momentum,
-                        self.output_result,
-                        &output_trade_log_name,
-                        &kline,
-                        None,
-                    );
-                }
+            self.signal.update(kline);
+            self.filters.update(kline);
+            if self.signal.momentum().len() >= 2 {
+                open_trade(
+                    symbol.clone(),
+                    &mut metric,
+                    &mut self.config,
+                    &mut trades,
+                    self.signal.momentum(),
+                    &self.filters,
+                    self.output_result,
+                    &output_trade_log_name,
+                    &kline,
+                    None,
+                    None,
+                );
             }
+            metric.record_equity(&trades, kline);
         }
         metric
     }
 
-    pub fn add_momentum(&mut self, momentum: f64) {
-        self.momentum.push_back(momentum);
-        if self.momentum.len() > self.config.look_back_count as usize {
-            self.momentum.pop_front();
-        }
-    }
-
     pub fn trade_log(&self, metric: &mut BacktestMetric, trade: &Trade, curr_kline: &Kline) {
-        let curr_date = NaiveDateTime::from_timestamp_millis(curr_kline.close_timestamp).unwrap();
-        let entry_date = NaiveDateTime::from_timestamp_millis(trade.entry_ts).unwrap();
+        let curr_date = DateTime::from_timestamp_millis(curr_kline.close_timestamp)
+            .unwrap()
+            .naive_utc();
+        let entry_date = DateTime::from_timestamp_millis(trade.entry_ts)
+            .unwrap()
+            .naive_utc();
         metric.max_usd = metric.max_usd.max(metric.usd_balance);
         metric.min_usd = metric.min_usd.min(metric.usd_balance);
         let mut msg = "".to_string();
@@ -190,3 +431,43 @@ impl Backtest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY_MS: i64 = 86_400_000;
+
+    fn daily_curve(equities: &[f64]) -> Vec<EquityPoint> {
+        equities
+            .iter()
+            .enumerate()
+            .map(|(index, equity)| EquityPoint {
+                timestamp: index as i64 * DAY_MS,
+                usd_balance: *equity,
+                equity: *equity,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sharpe_ratio_of_daily_returns_is_annualized() {
+        // Returns 0.1, -0.1 and 0.1: mean 1/30, standard deviation sqrt(0.08) / 3
+        let sharpe = sharpe_ratio(&daily_curve(&[100., 110., 99., 108.9]));
+        let expected = (1. / 30.) / (0.08f64.sqrt() / 3.) * 365f64.sqrt();
+        assert!((sharpe - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sharpe_ratio_is_zero_without_variance_or_returns() {
+        assert_eq!(sharpe_ratio(&daily_curve(&[100., 100., 100.])), 0.);
+        assert_eq!(sharpe_ratio(&daily_curve(&[100., 110.])), 0.);
+        assert_eq!(sharpe_ratio(&[]), 0.);
+    }
+
+    #[test]
+    fn sharpe_ratio_sign_follows_the_trend() {
+        assert!(sharpe_ratio(&daily_curve(&[100., 102., 101., 104., 105.])) > 0.);
+        assert!(sharpe_ratio(&daily_curve(&[100., 98., 99., 96., 95.])) < 0.);
+    }
+}
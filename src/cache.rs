@@ -0,0 +1,199 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::Path,
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use trade_utils::types::kline::Kline;
+
+use crate::{backtest::BacktestSummary, types::BacktestConfig};
+
+pub const CACHE_PATH: &str = "./hypertune_cache.jsonl";
+// Hash of the package version, src, Cargo.lock, trade_utils and the features, set by build.rs
+pub const CODE_VERSION: &str = env!("MOMENTUM_CODE_VERSION");
+
+/// One line of the cache file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub key: String,
+    pub config: BacktestConfig,
+    pub metrics: BacktestSummary,
+}
+
+/// Hypertune results by `cache_key`, every result is appended to the file as soon as it is
+/// computed so an interrupted run resumes where it stopped.
+pub struct ResultCache {
+    entries: HashMap<String, BacktestSummary>,
+    file: File,
+}
+
+/// Hash of the klines the configs are backtested on, so a changed range or re-synced klines
+/// miss the cache.
+pub fn data_hash(symbol: &str, klines: &[Kline], regime_klines: &[Kline]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(symbol.as_bytes());
+    for kline_set in [klines, regime_klines] {
+        hasher.update(serde_json::to_vec(kline_set).unwrap());
+    }
+    hex::encode(hasher.finalize())
+}
+
+pub fn cache_key(config: &BacktestConfig, data_hash: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(CODE_VERSION.as_bytes());
+    hasher.update(data_hash.as_bytes());
+    hasher.update(serde_json::to_vec(config).unwrap());
+    hex::encode(hasher.finalize())
+}
+
+impl ResultCache {
+    pub fn open(path: &Path) -> io::Result<ResultCache> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let mut entries = HashMap::new();
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            // The last line is cut when the run was killed while writing it
+            match serde_json::from_str::<CacheEntry>(line) {
+                Ok(entry) => {
+                    entries.insert(entry.key, entry.metrics);
+                }
+                Err(err) => warn!("Skip cache line of {:?}, {}", path, err),
+            }
+        }
+        info!("{} cached results in {:?}", entries.len(), path);
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if !content.is_empty() && !content.ends_with('\n') {
+            file.write_all(b"\n")?;
+        }
+        Ok(ResultCache { entries, file })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&BacktestSummary> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(
+        &mut self,
+        key: String,
+        config: &BacktestConfig,
+        metrics: &BacktestSummary,
+    ) -> io::Result<()> {
+        let entry = CacheEntry {
+            key,
+            config: config.clone(),
+            metrics: metrics.clone(),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.flush()?;
+        self.entries.insert(entry.key, entry.metrics);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::backtest::BacktestMetric;
+
+    fn config(look_back_count: usize) -> BacktestConfig {
+        serde_json::from_value(json!({
+            "initial_captial": 1000.0,
+            "fee_rate": 0.0004,
+            "entry_portion": 0.5,
+            "look_back_count": look_back_count,
+            "risk_portion": 0.05,
+            "tp_ratio": 2.0
+        }))
+        .unwrap()
+    }
+
+    fn kline(close: f64) -> Kline {
+        Kline {
+            open_timestamp: 0,
+            close_timestamp: 86_399_999,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: 1.,
+        }
+    }
+
+    fn summary(config: &BacktestConfig) -> BacktestSummary {
+        BacktestMetric::new(config).summary()
+    }
+
+    #[test]
+    fn cache_key_is_stable() {
+        let data_hash = data_hash("BTCUSDT", &[kline(100.)], &[]);
+        assert_eq!(data_hash, super::data_hash("BTCUSDT", &[kline(100.)], &[]));
+        // A config read back from its json, like the configs of a resumed hypertune
+        let reread: BacktestConfig =
+            serde_json::from_str(&serde_json::to_string(&config(8)).unwrap()).unwrap();
+        assert_eq!(
+            cache_key(&config(8), &data_hash),
+            cache_key(&reread, &data_hash)
+        );
+        assert_ne!(
+            cache_key(&config(8), &data_hash),
+            cache_key(&config(9), &data_hash)
+        );
+        for other_hash in [
+            super::data_hash("ETHUSDT", &[kline(100.)], &[]),
+            super::data_hash("BTCUSDT", &[kline(101.)], &[]),
+            super::data_hash("BTCUSDT", &[kline(100.)], &[kline(100.)]),
+        ] {
+            assert_ne!(data_hash, other_hash);
+            assert_ne!(
+                cache_key(&config(8), &data_hash),
+                cache_key(&config(8), &other_hash)
+            );
+        }
+    }
+
+    #[test]
+    fn open_recovers_from_a_cut_last_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.jsonl");
+        let mut cache = ResultCache::open(&path).unwrap();
+        for look_back_count in [8, 9] {
+            let config = config(look_back_count);
+            cache
+                .insert(look_back_count.to_string(), &config, &summary(&config))
+                .unwrap();
+        }
+        drop(cache);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"key\":\"10\",\"config\":{{\"initial_").unwrap();
+
+        let mut cache = ResultCache::open(&path).unwrap();
+        assert!(cache.get("8").is_some() && cache.get("9").is_some());
+        assert!(cache.get("10").is_none());
+        // The next line starts after the cut one
+        let config = config(10);
+        cache
+            .insert("10".to_owned(), &config, &summary(&config))
+            .unwrap();
+        let cache = ResultCache::open(&path).unwrap();
+        for key in ["8", "9", "10"] {
+            assert!(cache.get(key).is_some(), "{}", key);
+        }
+    }
+
+    #[test]
+    fn open_reports_an_unreadable_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(ResultCache::open(dir.path()).is_err());
+    }
+}
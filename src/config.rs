@@ -0,0 +1,663 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::NaiveDateTime;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+use crate::{
+    filter::FilterConfig,
+    monte_carlo::MonteCarloConfig,
+    risk::RiskConfig,
+    secrets::{API_KEY_ENV, SECRET_KEY_ENV},
+    types::{BacktestConfig, BacktestSettingConfig, LiveSettingConfig},
+};
+
+pub const DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+pub const EXTENDS: &str = "extends"; // Path of the base config
+pub const PROFILES: &str = "profiles"; // Named overrides of the config
+pub const SETTING_PREFIX: &str = "setting.";
+const MAX_EXTENDS_DEPTH: usize = 16;
+// The kline loop waits for a post-only entry, it should not miss the next kline
+const MAX_LIMIT_TIMEOUT_SECS: f64 = 300.;
+
+pub enum ConfigError {
+    MissingCredentials {
+        path: PathBuf,
+    },
+    InvalidOverride(String),
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Syntax {
+        path: PathBuf,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    Parse {
+        path: PathBuf,
+        field: String,
+        source: serde_json::Error,
+    },
+    Invalid {
+        path: PathBuf,
+        field: String,
+        reason: String,
+    },
+    // A command line argument out of its range
+    InvalidArg {
+        field: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::MissingCredentials { path } => write!(
+                f,
+                "no credentials, set {} and {} or create {} with api_key and secret_key",
+                API_KEY_ENV,
+                SECRET_KEY_ENV,
+                path.display()
+            ),
+            ConfigError::InvalidOverride(set) => {
+                write!(f, "invalid override `{}`, expected key=value", set)
+            }
+            ConfigError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Syntax { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Parse {
+                path,
+                field,
+                source,
+            } => write!(f, "{}: field `{}`: {}", path.display(), field, source),
+            ConfigError::Invalid {
+                path,
+                field,
+                reason,
+            } => write!(f, "{}: field `{}` {}", path.display(), field, reason),
+            ConfigError::InvalidArg { field, reason } => {
+                write!(f, "argument `--{}` {}", field.replace('_', "-"), reason)
+            }
+        }
+    }
+}
+
+// Shown when main returns the error
+impl fmt::Debug for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            ConfigError::Syntax { source, .. } => Some(source.as_ref()),
+            ConfigError::Parse { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A field out of its valid range.
+#[derive(Debug)]
+pub struct InvalidField {
+    pub field: String,
+    pub reason: String,
+}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), InvalidField>;
+}
+
+fn check(valid: bool, field: &str, reason: &str) -> Result<(), InvalidField> {
+    if valid {
+        Ok(())
+    } else {
+        Err(InvalidField {
+            field: field.to_owned(),
+            reason: reason.to_owned(),
+        })
+    }
+}
+
+fn check_min(value: usize, min: usize, field: &str) -> Result<(), InvalidField> {
+    check(
+        value >= min,
+        field,
+        &format!("must be >= {}, got {}", min, value),
+    )
+}
+
+fn check_portion(value: f64, field: &str) -> Result<(), InvalidField> {
+    check(
+        value > 0. && value <= 1.,
+        field,
+        &format!("must be in (0, 1], got {}", value),
+    )
+}
+
+fn check_non_negative(value: f64, field: &str) -> Result<(), InvalidField> {
+    check(value >= 0., field, &format!("must be >= 0, got {}", value))
+}
+
+fn check_datetime(value: &str, field: &str) -> Result<NaiveDateTime, InvalidField> {
+    NaiveDateTime::parse_from_str(value, DATETIME_FORMAT).map_err(|err| InvalidField {
+        field: field.to_owned(),
+        reason: format!("must be {}, {}", DATETIME_FORMAT, err),
+    })
+}
+
+impl Validate for BacktestConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        check(self.initial_captial > 0., "initial_captial", "must be > 0")?;
+        check_non_negative(self.fee_rate, "fee_rate")?;
+        check_portion(self.entry_portion, "entry_portion")?;
+        check_min(self.look_back_count, 2, "look_back_count")?;
+        check_portion(self.risk_portion, "risk_portion")?;
+        check(self.tp_ratio > 0., "tp_ratio", "must be > 0")?;
+        // An EMA of period 1 is the value itself
+        check(
+            self.signal_smoothing != 1,
+            "signal_smoothing",
+            "must be 0 or >= 2",
+        )?;
+        check_non_negative(self.signal_threshold, "signal_threshold")?;
+        for (index, filter) in self.filters.iter().enumerate() {
+            let period = match filter {
+                FilterConfig::Trend { period }
+                | FilterConfig::Adx { period, .. }
+                | FilterConfig::Rsi { period, .. }
+                | FilterConfig::Volatility { period, .. }
+                | FilterConfig::Bollinger { period, .. }
+                | FilterConfig::Donchian { period }
+                | FilterConfig::Volume { period, .. } => *period,
+            };
+            check(
+                period >= 1,
+                &format!("filters[{}].period", index),
+                "must be >= 1",
+            )?;
+        }
+        if let Some(regime_collection) = &self.regime_collection {
+            // Live trade gets the regime klines of the symbol and interval
+            check(
+                regime_collection.split_once('_').is_some(),
+                "regime_collection",
+                "must be symbol_interval, e.g. BTCUSDT_1d",
+            )?;
+        }
+        check(
+            self.regime_ema_period != 1,
+            "regime_ema_period",
+            "must be 0 or >= 2",
+        )?;
+        check_min(self.regime_bars, 1, "regime_bars")?;
+        check_min(self.regime_slope_bars, 1, "regime_slope_bars")?;
+        check(self.max_entries >= 1, "max_entries", "must be >= 1")?;
+        check_portion(self.scale_in_decay, "scale_in_decay")?;
+        check(
+            self.trailing_stop_pct >= 0. && self.trailing_stop_pct < 1.,
+            "trailing_stop_pct",
+            "must be in [0, 1)",
+        )?;
+        check_non_negative(self.trailing_stop_atr, "trailing_stop_atr")?;
+        check_min(self.atr_period, 1, "atr_period")?;
+        check_non_negative(self.break_even_r, "break_even_r")?;
+        for (index, tp_level) in self.tp_levels.iter().enumerate() {
+            check(
+                tp_level.r_multiple > 0.,
+                &format!("tp_levels[{}].r_multiple", index),
+                "must be > 0",
+            )?;
+            check_portion(tp_level.fraction, &format!("tp_levels[{}].fraction", index))?;
+        }
+        // The last level takes the rest, so the levels before it must leave some
+        if let Some((_, taken)) = self.tp_levels.split_last() {
+            check(
+                taken.iter().map(|tp_level| tp_level.fraction).sum::<f64>() < 1.,
+                "tp_levels",
+                "fractions before the last level must sum to < 1",
+            )?;
+        }
+        check_non_negative(self.slippage_bps, "slippage_bps")?;
+        check_non_negative(self.spread_bps, "spread_bps")?;
+        check_non_negative(self.volume_impact, "volume_impact")?;
+        check_non_negative(self.limit_offset_bps, "limit_offset_bps")?;
+        check(
+            self.limit_timeout_secs >= 0. && self.limit_timeout_secs <= MAX_LIMIT_TIMEOUT_SECS,
+            "limit_timeout_secs",
+            &format!("must be in [0, {}]", MAX_LIMIT_TIMEOUT_SECS),
+        )?;
+        if let Some(maker_fee_rate) = self.maker_fee_rate {
+            check_non_negative(maker_fee_rate, "maker_fee_rate")?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for BacktestSettingConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        let from = check_datetime(&self.from, "from")?;
+        let to = check_datetime(&self.to, "to")?;
+        check(from < to, "to", "must be after from")?;
+        check(!self.symbol.is_empty(), "symbol", "must not be empty")
+    }
+}
+
+impl Validate for MonteCarloConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        check_min(self.simulations, 1, "simulations")?;
+        check_min(self.block_size, 1, "block_size")?;
+        check_portion(self.confidence, "confidence")?;
+        check(
+            self.ruin_ratio >= 0. && self.ruin_ratio <= 1.,
+            "ruin_ratio",
+            &format!("must be in [0, 1], got {}", self.ruin_ratio),
+        )
+    }
+}
+
+impl Validate for RiskConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        if let Some(max_daily_loss) = self.max_daily_loss {
+            check_portion(max_daily_loss, "risk.max_daily_loss")?;
+        }
+        if let Some(max_drawdown) = self.max_drawdown {
+            check_portion(max_drawdown, "risk.max_drawdown")?;
+        }
+        if let Some(max_position_notional) = self.max_position_notional {
+            check(
+                max_position_notional > 0.,
+                "risk.max_position_notional",
+                "must be > 0",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Validate for LiveSettingConfig {
+    fn validate(&self) -> Result<(), InvalidField> {
+        check(!self.version.is_empty(), "version", "must not be empty")?;
+        check(!self.symbol.is_empty(), "symbol", "must not be empty")?;
+        check(
+            self.api_key.is_none() && self.secret_key.is_none(),
+            "api_key",
+            "must not be in the setting config, move the credentials to the environment or secrets_path",
+        )?;
+        self.risk.validate()
+    }
+}
+
+/// Parses a json, toml or yaml file by its extension.
+fn parse_file(path: &Path) -> Result<Value, ConfigError> {
+    let text = fs::read_to_string(path).map_err(|source| ConfigError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let value: Result<Value, Box<dyn Error + Send + Sync>> = match extension {
+        Some("toml") => toml::from_str(&text).map_err(|err| err.into()),
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&text).map_err(|err| err.into()),
+        _ => serde_json::from_str(&text).map_err(|err| err.into()),
+    };
+    value.map_err(|source| ConfigError::Syntax {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+/// Objects are merged field by field, anything else is replaced.
+fn merge(base: &mut Value, value: Value) {
+    match (base, value) {
+        (Value::Object(base), Value::Object(value)) => {
+            for (key, value) in value {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, value) => *base = value,
+    }
+}
+
+/// The file merged onto the file in its `extends`, relative to its directory.
+fn parse_extended(path: &Path, depth: usize) -> Result<Value, ConfigError> {
+    let mut value = parse_file(path)?;
+    let extends = match value
+        .as_object_mut()
+        .and_then(|object| object.remove(EXTENDS))
+    {
+        Some(extends) => extends,
+        None => return Ok(value),
+    };
+    let invalid = |reason: &str| ConfigError::Invalid {
+        path: path.to_owned(),
+        field: EXTENDS.to_owned(),
+        reason: reason.to_owned(),
+    };
+    let base_path = match extends.as_str() {
+        Some(base_path) => path.parent().unwrap_or(Path::new("")).join(base_path),
+        None => return Err(invalid("must be a path")),
+    };
+    if depth >= MAX_EXTENDS_DEPTH {
+        return Err(invalid("is nested too deep, is there a cycle?"));
+    }
+    let mut base = parse_extended(&base_path, depth + 1)?;
+    merge(&mut base, value);
+    Ok(base)
+}
+
+/// Sets the dotted `key`, e.g. `risk.max_drawdown`, creating the objects on the way.
+fn set_field(value: &mut Value, key: &str, field_value: Value) {
+    let mut value = value;
+    for field in key.split('.') {
+        if !value.is_object() {
+            *value = Value::Object(Map::new());
+        }
+        value = value
+            .as_object_mut()
+            .unwrap()
+            .entry(field)
+            .or_insert(Value::Null);
+    }
+    *value = field_value;
+}
+
+/// `--set key=value` override, the value is parsed as json and taken as a string otherwise.
+#[derive(Debug, Clone)]
+pub struct ConfigOverride {
+    pub key: String,
+    pub value: Value,
+    pub text: String, // As given, for string fields whose value parses as json, e.g. version=2
+}
+
+/// Profile and `--set key=value` overrides from the cli. Keys prefixed with `setting.` override
+/// the setting config, the others the backtest config or hypertune spec.
+#[derive(Debug, Default, Clone)]
+pub struct ConfigOverrides {
+    pub profile: Option<String>,
+    pub config: Vec<ConfigOverride>,
+    pub setting: Vec<ConfigOverride>,
+}
+
+impl ConfigOverrides {
+    pub fn new(profile: Option<String>, sets: &[String]) -> Result<ConfigOverrides, ConfigError> {
+        let mut overrides = ConfigOverrides {
+            profile,
+            ..Default::default()
+        };
+        for set in sets {
+            let (key, text) = match set.split_once('=') {
+                Some((key, value)) if !key.is_empty() => (key.trim(), value.trim()),
+                _ => return Err(ConfigError::InvalidOverride(set.clone())),
+            };
+            let value = serde_json::from_str(text).unwrap_or(Value::String(text.to_owned()));
+            let (key, target) = match key.strip_prefix(SETTING_PREFIX) {
+                Some(key) => (key, &mut overrides.setting),
+                None => (key, &mut overrides.config),
+            };
+            target.push(ConfigOverride {
+                key: key.to_owned(),
+                value,
+                text: text.to_owned(),
+            });
+        }
+        Ok(overrides)
+    }
+
+    /// The backtest config or hypertune spec as a value, for the hypertune ranges.
+    pub fn config_value(&self, path: &Path) -> Result<Value, ConfigError> {
+        load_value(path, self.profile.as_deref(), &values(&self.config, &[]))
+    }
+
+    pub fn load_config<T: DeserializeOwned + Validate>(
+        &self,
+        path: &Path,
+    ) -> Result<T, ConfigError> {
+        self.load(path, &self.config)
+    }
+
+    pub fn load_setting<T: DeserializeOwned + Validate>(
+        &self,
+        path: &Path,
+    ) -> Result<T, ConfigError> {
+        self.load(path, &self.setting)
+    }
+
+    // An override the field can't take as json is retried as the string it was given
+    fn load<T: DeserializeOwned + Validate>(
+        &self,
+        path: &Path,
+        overrides: &[ConfigOverride],
+    ) -> Result<T, ConfigError> {
+        let mut as_text: Vec<&str> = Vec::new();
+        loop {
+            let value = load_value(path, self.profile.as_deref(), &values(overrides, &as_text))?;
+            let result = validated(path, value);
+            let retry = match &result {
+                Err(ConfigError::Parse { field, .. }) => overrides.iter().find(|o| {
+                    o.key == *field && !o.value.is_string() && !as_text.contains(&o.key.as_str())
+                }),
+                _ => None,
+            };
+            match retry {
+                Some(retry) => as_text.push(retry.key.as_str()),
+                None => return result,
+            }
+        }
+    }
+}
+
+// Override values with the keys of `as_text` as their given strings
+fn values(overrides: &[ConfigOverride], as_text: &[&str]) -> Vec<(String, Value)> {
+    overrides
+        .iter()
+        .map(|o| {
+            let value = if as_text.contains(&o.key.as_str()) {
+                Value::String(o.text.clone())
+            } else {
+                o.value.clone()
+            };
+            (o.key.clone(), value)
+        })
+        .collect()
+}
+
+/// The file with its `extends` chain, then the `profile` from its `profiles` and the overrides.
+/// Files without `profiles` ignore the profile.
+pub fn load_value(
+    path: &Path,
+    profile: Option<&str>,
+    overrides: &[(String, Value)],
+) -> Result<Value, ConfigError> {
+    let mut value = parse_extended(path, 0)?;
+    let profiles = value
+        .as_object_mut()
+        .and_then(|object| object.remove(PROFILES));
+    if let (Some(profile), Some(profiles)) = (profile, profiles) {
+        match profiles.get(profile) {
+            Some(profile_value) => merge(&mut value, profile_value.clone()),
+            None => {
+                let names: Vec<&String> = profiles
+                    .as_object()
+                    .map(|profiles| profiles.keys().collect())
+                    .unwrap_or_default();
+                return Err(ConfigError::Invalid {
+                    path: path.to_owned(),
+                    field: PROFILES.to_owned(),
+                    reason: format!("has no profile `{}`, available: {:?}", profile, names),
+                });
+            }
+        }
+    }
+    for (key, field_value) in overrides {
+        set_field(&mut value, key, field_value.clone());
+    }
+    Ok(value)
+}
+
+/// Deserializes the value of the file, the error points at the field that failed.
+pub fn from_value<T: DeserializeOwned>(path: &Path, value: Value) -> Result<T, ConfigError> {
+    serde_path_to_error::deserialize(value).map_err(|err| ConfigError::Parse {
+        path: path.to_owned(),
+        field: err.path().to_string(),
+        source: err.into_inner(),
+    })
+}
+
+/// Deserializes and validates the value of the file.
+pub fn validated<T: DeserializeOwned + Validate>(
+    path: &Path,
+    value: Value,
+) -> Result<T, ConfigError> {
+    let config: T = from_value(path, value)?;
+    config.validate().map_err(|invalid| ConfigError::Invalid {
+        path: path.to_owned(),
+        field: invalid.field,
+        reason: invalid.reason,
+    })?;
+    Ok(config)
+}
+
+/// Parses the json, toml or yaml file with its `extends` chain.
+pub fn read_config<T: DeserializeOwned>(path: &Path) -> Result<T, ConfigError> {
+    from_value(path, load_value(path, None, &[])?)
+}
+
+/// Parses and validates the config file.
+pub fn load_config<T: DeserializeOwned + Validate>(path: &Path) -> Result<T, ConfigError> {
+    validated(path, load_value(path, None, &[])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+
+    use super::*;
+
+    // Files of the test in their own temp dir, removed when it drops
+    fn write_configs(files: &[(&str, Value)]) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, value) in files {
+            fs::write(dir.path().join(name), value.to_string()).unwrap();
+        }
+        dir
+    }
+
+    fn overrides(profile: Option<&str>, sets: &[&str]) -> ConfigOverrides {
+        let sets: Vec<String> = sets.iter().map(|set| set.to_string()).collect();
+        ConfigOverrides::new(profile.map(str::to_owned), &sets).unwrap()
+    }
+
+    #[test]
+    fn overrides_apply_after_extends_and_profile() {
+        let dir = write_configs(&[
+            (
+                "base.json",
+                json!({"a": 1, "b": 1, "c": 1, "d": 1, "risk": {"x": 1, "y": 1}}),
+            ),
+            (
+                "config.json",
+                json!({
+                    "extends": "base.json",
+                    "b": 2, "c": 2, "d": 2,
+                    "risk": {"y": 2},
+                    "profiles": {"fast": {"c": 3, "d": 3}}
+                }),
+            ),
+        ]);
+        let value = overrides(Some("fast"), &["d=4", "risk.x=5", "setting.symbol=ETHUSDT"])
+            .config_value(&dir.path().join("config.json"))
+            .unwrap();
+        assert_eq!(
+            value,
+            json!({"a": 1, "b": 2, "c": 3, "d": 4, "risk": {"x": 5, "y": 2}})
+        );
+    }
+
+    #[test]
+    fn later_override_of_a_key_wins() {
+        let overrides = overrides(None, &["d=4", "setting.symbol=BTCUSDT", "d=6"]);
+        let dir = write_configs(&[("config.json", json!({"d": 1}))]);
+        let value = overrides
+            .config_value(&dir.path().join("config.json"))
+            .unwrap();
+        assert_eq!(value, json!({"d": 6}));
+        assert_eq!(overrides.setting.len(), 1);
+        assert_eq!(overrides.setting[0].key, "symbol");
+    }
+
+    #[derive(Deserialize)]
+    struct Named {
+        version: String,
+        count: usize,
+    }
+
+    impl Validate for Named {
+        fn validate(&self) -> Result<(), InvalidField> {
+            check_min(self.count, 1, "count")
+        }
+    }
+
+    #[test]
+    fn json_override_of_a_string_field_is_retried_as_text() {
+        let dir = write_configs(&[("config.json", json!({"version": "v1", "count": 1}))]);
+        let named: Named = overrides(None, &["version=2", "count=3"])
+            .load_config(&dir.path().join("config.json"))
+            .unwrap();
+        assert_eq!((named.version.as_str(), named.count), ("2", 3));
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() {
+        let dir = write_configs(&[(
+            "config.json",
+            json!({"d": 1, "profiles": {"fast": {"d": 2}}}),
+        )]);
+        let result = overrides(Some("slow"), &[]).config_value(&dir.path().join("config.json"));
+        assert!(matches!(result, Err(ConfigError::Invalid { field, .. }) if field == PROFILES));
+    }
+
+    #[test]
+    fn backtest_config_rejects_out_of_range_fields() {
+        let base = json!({
+            "initial_captial": 1000.0, "fee_rate": 0.0004, "entry_portion": 0.5,
+            "look_back_count": 2, "risk_portion": 0.05, "tp_ratio": 2.0, "candle_filter": false
+        });
+        let config: BacktestConfig = serde_json::from_value(base.clone()).unwrap();
+        assert!(config.validate().is_ok());
+        for (field, value) in [
+            ("maker_fee_rate", json!(-0.0002)),
+            ("signal_smoothing", json!(1)),
+            ("regime_ema_period", json!(1)),
+        ] {
+            let mut value_config = base.clone();
+            value_config[field] = value;
+            let config: BacktestConfig = serde_json::from_value(value_config).unwrap();
+            assert_eq!(config.validate().unwrap_err().field, field);
+        }
+    }
+
+    #[test]
+    fn integer_fields_reject_floats() {
+        let value = json!({
+            "initial_captial": 1000.0, "fee_rate": 0.0004, "entry_portion": 0.5,
+            "look_back_count": 8.0, "risk_portion": 0.05, "tp_ratio": 2.0, "candle_filter": false
+        });
+        let result: Result<BacktestConfig, _> = from_value(Path::new("config.json"), value);
+        assert!(
+            matches!(result, Err(ConfigError::Parse { field, .. }) if field == "look_back_count")
+        );
+    }
+}
@@ -3,5 +3,13 @@ pub const XRPUSDT_1D: &str = "XRPUSDT_1d";
 pub const DOGEUSDT_1D: &str = "DOGEUSDT_1d";
 pub const AVAXUSDT_1D: &str = "AVAXUSDT_1d";
 pub const MATICUSDT_1D: &str = "MATICUSDT_1d";
+// Collections of the symbols of `hypertune --basket`
+pub const BASKET_COLLECTIONS: [&str; 5] = [
+    BTCUSDT_1D,
+    XRPUSDT_1D,
+    DOGEUSDT_1D,
+    AVAXUSDT_1D,
+    MATICUSDT_1D,
+];
 pub const KLINE_DB: &str = "klines";
 pub const LOCAL_MONGO_CONNECTION_STRING: &str = "mongodb://localhost:27017";
@@ -0,0 +1,339 @@
+use std::{fs::File, path::Path};
+
+use chrono::DateTime;
+use log::*;
+use serde::{Deserialize, Serialize};
+use trade_utils::types::trade::TradeSide;
+
+use crate::{backtest::TradeRecord, types::ManagedTrade};
+
+/// Exchange fill, e.g. from the trade history export of the account.
+#[derive(Debug, Deserialize, Clone)]
+pub struct Fill {
+    pub timestamp: i64, // ms
+    pub side: String,   // BUY or SELL
+    pub price: f64,
+    pub quantity: f64,
+    #[serde(default)]
+    pub fee: f64,
+    #[serde(default)]
+    pub realized_profit: f64,
+}
+
+impl Fill {
+    fn is_buy(&self) -> bool {
+        self.side.eq_ignore_ascii_case("buy")
+    }
+}
+
+pub fn read_fills(path: &Path) -> Vec<Fill> {
+    let file = File::open(path).unwrap();
+    let mut reader = csv::Reader::from_reader(file);
+    let mut fills: Vec<Fill> = reader.deserialize().map(|fill| fill.unwrap()).collect();
+    fills.sort_by_key(|fill| fill.timestamp);
+    fills
+}
+
+/// A trade as the live trade saw it, rebuilt from the logged snapshots.
+#[derive(Debug, Clone)]
+pub struct LiveTrade {
+    pub trade: ManagedTrade,  // Last logged state
+    pub exit_ts: Option<i64>, // First snapshot without the trade
+}
+
+/// Trades are keyed by entry_ts and side, a trade is closed by the first snapshot without it.
+pub fn live_trades(snapshots: &[(i64, Vec<ManagedTrade>)]) -> Vec<LiveTrade> {
+    let mut live_trades: Vec<LiveTrade> = Vec::new();
+    let mut open: Vec<usize> = Vec::new();
+    for (timestamp, trades) in snapshots {
+        let mut still_open = Vec::new();
+        for trade in trades {
+            let index = open.iter().cloned().find(|index| {
+                let live_trade = &live_trades[*index].trade;
+                live_trade.entry_ts == trade.entry_ts && live_trade.entry_side == trade.entry_side
+            });
+            match index {
+                Some(index) => {
+                    live_trades[index].trade = trade.clone();
+                    still_open.push(index);
+                }
+                None => {
+                    live_trades.push(LiveTrade {
+                        trade: trade.clone(),
+                        exit_ts: None,
+                    });
+                    still_open.push(live_trades.len() - 1);
+                }
+            }
+        }
+        for index in open.iter() {
+            if !still_open.contains(index) {
+                live_trades[*index].exit_ts = Some(*timestamp);
+            }
+        }
+        open = still_open;
+    }
+    live_trades
+}
+
+/// A backtest trade with its exits merged.
+#[derive(Debug, Clone)]
+struct BacktestTrade {
+    entry_ts: i64,
+    entry_side: TradeSide,
+    entry_price: f64,
+    position: f64,
+    profit: f64, // Net of the entry and exit fees, like the live profit
+}
+
+fn backtest_trades(records: &[TradeRecord]) -> Vec<BacktestTrade> {
+    let mut trades: Vec<BacktestTrade> = Vec::new();
+    for record in records {
+        let trade = trades.iter_mut().find(|trade| {
+            trade.entry_ts == record.entry_ts && trade.entry_side == record.entry_side
+        });
+        match trade {
+            Some(trade) => {
+                trade.position += record.position;
+                trade.profit += record.profit - record.fee - record.entry_fee;
+            }
+            None => trades.push(BacktestTrade {
+                entry_ts: record.entry_ts,
+                entry_side: record.entry_side.clone(),
+                entry_price: record.entry_price,
+                position: record.position,
+                profit: record.profit - record.fee - record.entry_fee,
+            }),
+        }
+    }
+    trades
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct DivergenceRow {
+    pub entry_side: String,
+    pub live_entry_date: Option<String>,
+    pub backtest_entry_date: Option<String>,
+    pub entry_time_diff_mins: Option<f64>,
+    pub live_entry_price: Option<f64>, // Average entry fill price, the decision price without fills
+    pub backtest_entry_price: Option<f64>,
+    pub entry_price_diff: Option<f64>,
+    pub live_position: Option<f64>,
+    pub backtest_position: Option<f64>,
+    pub position_diff: Option<f64>,
+    pub live_fills: bool, // Without fills the live profit is unknown and not compared
+    pub live_profit: Option<f64>,
+    pub backtest_profit: Option<f64>,
+    pub profit_diff: Option<f64>,
+    pub slippage_bps: Option<f64>, // Entry fill against the decision price, positive is adverse
+    pub slippage_usd: Option<f64>,
+}
+
+#[derive(Debug, Default)]
+pub struct DivergenceReport {
+    pub rows: Vec<DivergenceRow>,
+    pub matched: usize,
+    pub live_only: usize,
+    pub backtest_only: usize,
+    pub without_fills: usize, // Live trades without exchange fills, only their entries are compared
+    pub mean_slippage_bps: f64,
+    pub total_slippage_usd: f64,
+    pub total_profit_diff: f64, // live - backtest of the matched trades with fills
+}
+
+impl DivergenceReport {
+    pub fn log(&self) {
+        info!(
+            "matched: {}, live_only: {}, backtest_only: {}, without_fills: {}, mean_slippage_bps: {:.4}, total_slippage_usd: {:.4}, total_profit_diff: {:.4}",
+            self.matched,
+            self.live_only,
+            self.backtest_only,
+            self.without_fills,
+            self.mean_slippage_bps,
+            self.total_slippage_usd,
+            self.total_profit_diff
+        );
+    }
+
+    pub fn write_csv(&self, path: &Path) {
+        let file = File::create(path).unwrap();
+        let mut writer = csv::Writer::from_writer(file);
+        for row in self.rows.iter() {
+            writer.serialize(row).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+}
+
+fn date(timestamp: i64) -> String {
+    DateTime::from_timestamp_millis(timestamp)
+        .unwrap()
+        .naive_utc()
+        .to_string()
+}
+
+/// Live fill summary of a trade, fills are taken once in time order. Entry fills are on the
+/// entry side within `fill_window_ms` after entry_ts, exit fills are on the other side until
+/// `fill_window_ms` after the trade disappears and at most the entry quantity.
+struct LiveFills {
+    entry_price: f64,
+    quantity: f64,
+    profit: f64, // Realized profit net of all fees
+}
+
+fn take_fills(
+    live_trade: &LiveTrade,
+    fills: &[Fill],
+    used: &mut [bool],
+    fill_window_ms: i64,
+) -> Option<LiveFills> {
+    let trade = &live_trade.trade;
+    let is_buy = trade.entry_side == TradeSide::Buy;
+    let mut quantity = 0.;
+    let mut notional = 0.;
+    let mut profit = 0.;
+    for (index, fill) in fills.iter().enumerate() {
+        if used[index]
+            || fill.is_buy() != is_buy
+            || fill.timestamp < trade.entry_ts
+            || fill.timestamp > trade.entry_ts + fill_window_ms
+        {
+            continue;
+        }
+        used[index] = true;
+        quantity += fill.quantity;
+        notional += fill.quantity * fill.price;
+        profit -= fill.fee;
+    }
+    if quantity == 0. {
+        return None;
+    }
+    let exit_end = live_trade.exit_ts.unwrap_or(i64::MAX - fill_window_ms) + fill_window_ms;
+    let mut exit_quantity = 0.;
+    for (index, fill) in fills.iter().enumerate() {
+        if exit_quantity >= quantity * (1. - 1e-9) {
+            break;
+        }
+        if used[index]
+            || fill.is_buy() == is_buy
+            || fill.timestamp < trade.entry_ts
+            || fill.timestamp > exit_end
+        {
+            continue;
+        }
+        used[index] = true;
+        exit_quantity += fill.quantity;
+        profit += fill.realized_profit - fill.fee;
+    }
+    Some(LiveFills {
+        entry_price: notional / quantity,
+        quantity,
+        profit,
+    })
+}
+
+/// Matches every live trade with the backtest trade of the same side whose entry is the closest
+/// within `match_window_ms`. Without fills the live entry falls back to the logged trade and its
+/// profit is left out, the logged trade misses the final exit.
+pub fn divergence_report(
+    live_trades: &[LiveTrade],
+    fills: &[Fill],
+    records: &[TradeRecord],
+    fill_window_ms: i64,
+    match_window_ms: i64,
+) -> DivergenceReport {
+    let mut report = DivergenceReport::default();
+    let backtest_trades = backtest_trades(records);
+    let mut matched = vec![false; backtest_trades.len()];
+    let mut used_fills = vec![false; fills.len()];
+    let mut slippage_count = 0;
+    for live_trade in live_trades {
+        let trade = &live_trade.trade;
+        let live_fills = take_fills(live_trade, fills, &mut used_fills, fill_window_ms);
+        let (entry_price, position, profit) = match &live_fills {
+            Some(live_fills) => (
+                live_fills.entry_price,
+                live_fills.quantity,
+                Some(live_fills.profit),
+            ),
+            None => {
+                report.without_fills += 1;
+                (trade.entry_price, trade.initial_position(), None)
+            }
+        };
+        let mut row = DivergenceRow {
+            entry_side: format!("{:?}", trade.entry_side),
+            live_entry_date: Some(date(trade.entry_ts)),
+            live_entry_price: Some(entry_price),
+            live_position: Some(position),
+            live_fills: live_fills.is_some(),
+            live_profit: profit,
+            ..Default::default()
+        };
+        if live_fills.is_some() {
+            let sign = if trade.entry_side == TradeSide::Buy {
+                1.
+            } else {
+                -1.
+            };
+            let slippage = sign * (entry_price - trade.entry_price);
+            row.slippage_bps = Some(slippage / trade.entry_price * 10000.);
+            row.slippage_usd = Some(slippage * position);
+            report.mean_slippage_bps += slippage / trade.entry_price * 10000.;
+            report.total_slippage_usd += slippage * position;
+            slippage_count += 1;
+        }
+
+        let closest = backtest_trades
+            .iter()
+            .enumerate()
+            .filter(|(index, backtest_trade)| {
+                !matched[*index]
+                    && backtest_trade.entry_side == trade.entry_side
+                    && (backtest_trade.entry_ts - trade.entry_ts).abs() <= match_window_ms
+            })
+            .min_by_key(|(_, backtest_trade)| (backtest_trade.entry_ts - trade.entry_ts).abs());
+        match closest {
+            Some((index, backtest_trade)) => {
+                matched[index] = true;
+                report.matched += 1;
+                row.backtest_entry_date = Some(date(backtest_trade.entry_ts));
+                row.entry_time_diff_mins =
+                    Some((trade.entry_ts - backtest_trade.entry_ts) as f64 / 60000.);
+                row.backtest_entry_price = Some(backtest_trade.entry_price);
+                row.entry_price_diff = Some(entry_price - backtest_trade.entry_price);
+                row.backtest_position = Some(backtest_trade.position);
+                row.position_diff = Some(position - backtest_trade.position);
+                row.backtest_profit = Some(backtest_trade.profit);
+                if let Some(profit) = profit {
+                    report.total_profit_diff += profit - backtest_trade.profit;
+                    row.profit_diff = Some(profit - backtest_trade.profit);
+                }
+            }
+            None => {
+                report.live_only += 1;
+                warn!("Live trade has no backtest trade: {:?}", trade);
+            }
+        }
+        report.rows.push(row);
+    }
+    for (index, backtest_trade) in backtest_trades.iter().enumerate() {
+        if matched[index] {
+            continue;
+        }
+        report.backtest_only += 1;
+        warn!("Backtest trade has no live trade: {:?}", backtest_trade);
+        report.rows.push(DivergenceRow {
+            entry_side: format!("{:?}", backtest_trade.entry_side),
+            backtest_entry_date: Some(date(backtest_trade.entry_ts)),
+            backtest_entry_price: Some(backtest_trade.entry_price),
+            backtest_position: Some(backtest_trade.position),
+            backtest_profit: Some(backtest_trade.profit),
+            ..Default::default()
+        });
+    }
+    if slippage_count > 0 {
+        report.mean_slippage_bps /= slippage_count as f64;
+    }
+    report
+}
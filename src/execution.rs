@@ -0,0 +1,250 @@
+use serde::{Deserialize, Serialize};
+use trade_utils::types::kline::Kline;
+
+use crate::{
+    backtest::BacktestMetric,
+    types::{BacktestConfig, EntryOrder, FillTiming},
+};
+
+/// Price and fee rate of an entry.
+#[derive(Debug, Clone, Copy)]
+pub struct EntryFill {
+    pub price: f64,
+    pub fee_rate: f64,
+}
+
+/// Filled position and average price of a live limit entry, journaled so replay enters the
+/// same trade. A position of 0 is an entry that was not filled.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LiveFill {
+    pub position: f64,
+    pub entry_price: f64,
+}
+
+/// Price a market order decided on the close of `kline` fills at, the next open with
+/// `fill_timing` next_open. Only the backtest simulates it, live fills are the exchange's.
+pub fn market_price(metric: &BacktestMetric, config: &BacktestConfig, kline: &Kline) -> f64 {
+    match (
+        metric.simulate_fills,
+        &config.fill_timing,
+        &metric.next_kline,
+    ) {
+        (true, FillTiming::NextOpen, Some(next_kline)) => next_kline.open,
+        _ => kline.close,
+    }
+}
+
+/// Limit price of an entry decided on `close`, `limit_offset_bps` in favor of the entry.
+pub fn limit_price(config: &BacktestConfig, is_buy: bool, close: f64) -> f64 {
+    if is_buy {
+        close * (1. - config.limit_offset_bps / 10000.)
+    } else {
+        close * (1. + config.limit_offset_bps / 10000.)
+    }
+}
+
+/// Entry of `position` decided on the close of `kline`, None if a limit entry is not filled and
+/// there is no fallback. The backtest fills a post-only order only when the next kline trades
+/// through the limit price, an IOC order only when the next kline opens at or better than it,
+/// and the fallback market order at the next close, after the post-only order waited the whole
+/// kline. Live trade gets the order prices, the exchange fills them.
+pub fn entry_fill(
+    metric: &BacktestMetric,
+    config: &BacktestConfig,
+    is_buy: bool,
+    position: f64,
+    kline: &Kline,
+) -> Option<EntryFill> {
+    let maker_fee_rate = config.maker_fee_rate.unwrap_or(config.fee_rate);
+    let market_fill = |price: f64| EntryFill {
+        price: fill_price(metric, config, is_buy, price, position, kline),
+        fee_rate: config.fee_rate,
+    };
+    let limit = limit_price(config, is_buy, kline.close);
+    match (&config.entry_order, metric.simulate_fills) {
+        (EntryOrder::Market, _) => Some(market_fill(market_price(metric, config, kline))),
+        (EntryOrder::PostOnly, false) => Some(EntryFill {
+            price: limit,
+            fee_rate: maker_fee_rate,
+        }),
+        (EntryOrder::Ioc, false) => Some(EntryFill {
+            price: limit,
+            fee_rate: config.fee_rate,
+        }),
+        (EntryOrder::PostOnly, true) => {
+            let next_kline = metric.next_kline.as_ref()?;
+            if (is_buy && next_kline.low < limit) || (!is_buy && next_kline.high > limit) {
+                Some(EntryFill {
+                    price: limit,
+                    fee_rate: maker_fee_rate,
+                })
+            } else if config.limit_fallback_market {
+                Some(market_fill(next_kline.close))
+            } else {
+                None
+            }
+        }
+        (EntryOrder::Ioc, true) => {
+            let next_kline = metric.next_kline.as_ref()?;
+            if (is_buy && next_kline.open <= limit) || (!is_buy && next_kline.open >= limit) {
+                Some(EntryFill {
+                    price: next_kline.open,
+                    fee_rate: config.fee_rate,
+                })
+            } else if config.limit_fallback_market {
+                Some(market_fill(next_kline.open))
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// `price` moved against a fill of `position` by the fixed, spread and volume slippage.
+pub fn fill_price(
+    metric: &BacktestMetric,
+    config: &BacktestConfig,
+    is_buy: bool,
+    price: f64,
+    position: f64,
+    kline: &Kline,
+) -> f64 {
+    if !metric.simulate_fills {
+        return price;
+    }
+    let mut slippage = config.slippage_bps / 10000. + config.spread_bps / 20000.;
+    if kline.volume > 0. {
+        slippage += config.volume_impact * position / kline.volume;
+    }
+    if is_buy {
+        price * (1. + slippage)
+    } else {
+        price * (1. - slippage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    // The base config with `fields` replaced
+    fn config(fields: Value) -> BacktestConfig {
+        let mut value = json!({
+            "initial_captial": 1000.0,
+            "fee_rate": 0.0004,
+            "entry_portion": 0.5,
+            "look_back_count": 2,
+            "risk_portion": 0.05,
+            "tp_ratio": 2.0,
+            "candle_filter": false
+        });
+        for (key, field) in fields.as_object().unwrap() {
+            value[key] = field.clone();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn kline(open: f64, high: f64, low: f64, close: f64) -> Kline {
+        Kline {
+            open_timestamp: 0,
+            close_timestamp: 0,
+            open,
+            high,
+            low,
+            close,
+            volume: 100.,
+        }
+    }
+
+    // Backtest metric with the kline after the decision
+    fn metric(config: &BacktestConfig, next_kline: Option<Kline>) -> BacktestMetric {
+        let mut metric = BacktestMetric::new(config);
+        metric.simulate_fills = true;
+        metric.next_kline = next_kline;
+        metric
+    }
+
+    #[test]
+    fn slippage_moves_the_fill_against_the_order() {
+        let config =
+            config(json!({"slippage_bps": 10.0, "spread_bps": 4.0, "volume_impact": 0.01}));
+        let kline = kline(100., 100., 100., 100.);
+        // 10 bps, half of the 4 bps spread and 0.01 * 50 / 100 of the volume
+        let slippage = 0.001 + 0.0002 + 0.005;
+        let metric = metric(&config, None);
+        let buy = fill_price(&metric, &config, true, 100., 50., &kline);
+        let sell = fill_price(&metric, &config, false, 100., 50., &kline);
+        assert!((buy - 100. * (1. + slippage)).abs() < 1e-9);
+        assert!((sell - 100. * (1. - slippage)).abs() < 1e-9);
+        // Live fills are the exchange's
+        let live = BacktestMetric::new(&config);
+        assert_eq!(fill_price(&live, &config, true, 100., 50., &kline), 100.);
+    }
+
+    #[test]
+    fn next_open_fills_at_the_close_of_the_last_kline() {
+        let config = config(json!({"fill_timing": "next_open"}));
+        let next_kline = kline(102., 103., 101., 102.5);
+        let kline = kline(100., 100., 100., 100.);
+        assert_eq!(
+            market_price(&metric(&config, Some(next_kline)), &config, &kline),
+            102.
+        );
+        assert_eq!(market_price(&metric(&config, None), &config, &kline), 100.);
+    }
+
+    // (price, fee_rate) of a buy decided on a close of 100, the limit price is 99.9
+    fn buy_fill(config: &BacktestConfig, next_kline: Option<Kline>) -> Option<(f64, f64)> {
+        let kline = kline(100., 100., 100., 100.);
+        entry_fill(&metric(config, next_kline), config, true, 1., &kline)
+            .map(|fill| (fill.price, fill.fee_rate))
+    }
+
+    #[test]
+    fn post_only_fills_when_the_next_kline_trades_through_the_limit() {
+        let fields = json!({
+            "entry_order": "post_only", "limit_offset_bps": 10.0, "maker_fee_rate": 0.0002
+        });
+        let post_only = config(fields.clone());
+        let through = kline(100.2, 100.5, 99.5, 100.1);
+        assert_eq!(buy_fill(&post_only, Some(through)), Some((99.9, 0.0002)));
+        // Touching the limit price is not enough, the rest falls back to market at the close
+        let touch = kline(100.2, 100.5, 99.9, 100.1);
+        assert_eq!(
+            buy_fill(&post_only, Some(touch.clone())),
+            Some((100.1, 0.0004))
+        );
+        let mut no_fallback = fields;
+        no_fallback["limit_fallback_market"] = json!(false);
+        assert_eq!(buy_fill(&config(no_fallback), Some(touch)), None);
+        // No kline after the last one to fill in
+        assert_eq!(buy_fill(&post_only, None), None);
+    }
+
+    #[test]
+    fn ioc_fills_when_the_next_kline_opens_at_the_limit_or_better() {
+        let ioc = config(json!({"entry_order": "ioc", "limit_offset_bps": 10.0}));
+        let better = kline(99.8, 100.5, 99.5, 100.1);
+        assert_eq!(buy_fill(&ioc, Some(better)), Some((99.8, 0.0004)));
+        let worse = kline(100.2, 100.5, 99.5, 100.1);
+        assert_eq!(buy_fill(&ioc, Some(worse.clone())), Some((100.2, 0.0004)));
+        let no_fallback = config(json!({
+            "entry_order": "ioc", "limit_offset_bps": 10.0, "limit_fallback_market": false
+        }));
+        assert_eq!(buy_fill(&no_fallback, Some(worse)), None);
+        assert_eq!(buy_fill(&ioc, None), None);
+    }
+
+    #[test]
+    fn live_limit_entry_is_at_the_limit_price() {
+        let config = config(json!({
+            "entry_order": "post_only", "limit_offset_bps": 10.0, "maker_fee_rate": 0.0002
+        }));
+        let kline = kline(100., 100., 100., 100.);
+        let fill = entry_fill(&BacktestMetric::new(&config), &config, false, 1., &kline).unwrap();
+        assert!((fill.price - 100.1).abs() < 1e-9);
+        assert_eq!(fill.fee_rate, 0.0002);
+    }
+}
@@ -0,0 +1,211 @@
+use log::info;
+use serde::{Deserialize, Serialize};
+use trade_utils::types::{kline::Kline, trade::TradeSide};
+
+use crate::{
+    indicators::{Adx, Atr, Bollinger, Donchian, DonchianChannel, Ema, Indicator, Rsi, VolumeMa},
+    regime::RegimeFilter,
+    types::BacktestConfig,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FilterConfig {
+    /// Buy above the EMA, sell below it
+    Trend { period: usize },
+    /// Trade only when the ADX shows a strong enough trend
+    Adx { period: usize, min: f64 },
+    /// No buy above `overbought`, no sell below `oversold`
+    Rsi {
+        period: usize,
+        overbought: f64,
+        oversold: f64,
+    },
+    /// ATR / close within [min, max]
+    Volatility { period: usize, min: f64, max: f64 },
+    /// No buy above the upper band, no sell below the lower band
+    Bollinger { period: usize, std_dev: f64 },
+    /// Buy on a new high, sell on a new low
+    Donchian { period: usize },
+    /// Volume at least `min_ratio` of its average
+    Volume { period: usize, min_ratio: f64 },
+}
+
+enum EntryFilter {
+    Trend(Ema),
+    Adx(Adx, f64),
+    Rsi(Rsi, f64, f64),
+    Volatility(Atr, f64, f64),
+    Bollinger(Bollinger),
+    Donchian(Donchian, Option<DonchianChannel>), // The channel before the current kline
+    Volume(VolumeMa, f64),
+}
+
+/// Indicator and regime filters on the momentum entries, every filter has to allow the entry
+/// and entries are blocked until all filters are warmed up.
+pub struct EntryFilters {
+    filters: Vec<EntryFilter>,
+    regime: Option<RegimeFilter>,
+    own_regime: bool, // The regime comes from the traded klines instead of regime_collection
+    warmup_bars: usize,
+}
+
+impl EntryFilters {
+    pub fn new(config: &BacktestConfig) -> EntryFilters {
+        let mut warmup_bars = 0;
+        let filters = config
+            .filters
+            .iter()
+            .map(|config| match config {
+                FilterConfig::Trend { period } => {
+                    warmup_bars = warmup_bars.max(*period);
+                    EntryFilter::Trend(Ema::new(*period))
+                }
+                FilterConfig::Adx { period, min } => {
+                    warmup_bars = warmup_bars.max(2 * period + 1);
+                    EntryFilter::Adx(Adx::new(*period), *min)
+                }
+                FilterConfig::Rsi {
+                    period,
+                    overbought,
+                    oversold,
+                } => {
+                    warmup_bars = warmup_bars.max(period + 1);
+                    EntryFilter::Rsi(Rsi::new(*period), *overbought, *oversold)
+                }
+                FilterConfig::Volatility { period, min, max } => {
+                    warmup_bars = warmup_bars.max(*period);
+                    EntryFilter::Volatility(Atr::new(*period), *min, *max)
+                }
+                FilterConfig::Bollinger { period, std_dev } => {
+                    warmup_bars = warmup_bars.max(*period);
+                    EntryFilter::Bollinger(Bollinger::new(*period, *std_dev))
+                }
+                FilterConfig::Donchian { period } => {
+                    warmup_bars = warmup_bars.max(period + 1);
+                    EntryFilter::Donchian(Donchian::new(*period), None)
+                }
+                FilterConfig::Volume { period, min_ratio } => {
+                    warmup_bars = warmup_bars.max(*period);
+                    EntryFilter::Volume(VolumeMa::new(*period), *min_ratio)
+                }
+            })
+            .collect();
+        let regime = RegimeFilter::new(config);
+        let own_regime = config.regime_collection.is_none();
+        if let (Some(regime), true) = (&regime, own_regime) {
+            warmup_bars = warmup_bars.max(regime.warmup_bars());
+        }
+        EntryFilters {
+            filters,
+            regime,
+            own_regime,
+            warmup_bars,
+        }
+    }
+
+    /// Closed klines needed before every filter has a value.
+    pub fn warmup_bars(&self) -> usize {
+        self.warmup_bars
+    }
+
+    /// Closed `regime_collection` klines needed before the regime is known.
+    pub fn regime_warmup_bars(&self) -> usize {
+        match (&self.regime, self.own_regime) {
+            (Some(regime), false) => regime.warmup_bars(),
+            _ => 0,
+        }
+    }
+
+    /// Feeds the `regime_collection` klines, they have to be closed by the time of the
+    /// traded kline passed to the next `update`.
+    pub fn update_regime(&mut self, kline: &Kline) {
+        if let (Some(regime), false) = (self.regime.as_mut(), self.own_regime) {
+            regime.update(kline);
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) {
+        if let (Some(regime), true) = (self.regime.as_mut(), self.own_regime) {
+            regime.update(kline);
+        }
+        for filter in self.filters.iter_mut() {
+            match filter {
+                EntryFilter::Trend(ema) => {
+                    ema.update(kline);
+                }
+                EntryFilter::Adx(adx, _) => {
+                    adx.update(kline);
+                }
+                EntryFilter::Rsi(rsi, _, _) => {
+                    rsi.update(kline);
+                }
+                EntryFilter::Volatility(atr, _, _) => {
+                    atr.update(kline);
+                }
+                EntryFilter::Bollinger(bollinger) => {
+                    bollinger.update(kline);
+                }
+                EntryFilter::Donchian(donchian, prev_channel) => {
+                    *prev_channel = donchian.value();
+                    donchian.update(kline);
+                }
+                EntryFilter::Volume(volume_ma, _) => {
+                    volume_ma.update(kline);
+                }
+            }
+        }
+    }
+
+    pub fn allows(&self, entry_side: &TradeSide, kline: &Kline) -> bool {
+        let is_buy = *entry_side == TradeSide::Buy;
+        let allowed = self.filters.iter().all(|filter| {
+            let allowed = match filter {
+                EntryFilter::Trend(ema) => ema.value().is_some_and(|ema| {
+                    (is_buy && kline.close > ema) || (!is_buy && kline.close < ema)
+                }),
+                EntryFilter::Adx(adx, min) => adx.value().is_some_and(|adx| adx >= *min),
+                EntryFilter::Rsi(rsi, overbought, oversold) => rsi.value().is_some_and(|rsi| {
+                    (is_buy && rsi <= *overbought) || (!is_buy && rsi >= *oversold)
+                }),
+                EntryFilter::Volatility(atr, min, max) => atr.value().is_some_and(|atr| {
+                    let volatility = atr / kline.close;
+                    volatility >= *min && volatility <= *max
+                }),
+                EntryFilter::Bollinger(bollinger) => bollinger.value().is_some_and(|bands| {
+                    (is_buy && kline.close <= bands.upper)
+                        || (!is_buy && kline.close >= bands.lower)
+                }),
+                EntryFilter::Donchian(_, prev_channel) => prev_channel.is_some_and(|channel| {
+                    (is_buy && kline.close > channel.upper)
+                        || (!is_buy && kline.close < channel.lower)
+                }),
+                EntryFilter::Volume(volume_ma, min_ratio) => volume_ma
+                    .value()
+                    .is_some_and(|volume_ma| kline.volume >= min_ratio * volume_ma),
+            };
+            if !allowed {
+                info!("Entry {:?} is filtered by {}", entry_side, filter.name());
+            }
+            allowed
+        });
+        match &self.regime {
+            Some(regime) => allowed && regime.allows(entry_side),
+            None => allowed,
+        }
+    }
+}
+
+impl EntryFilter {
+    fn name(&self) -> &str {
+        match self {
+            EntryFilter::Trend(_) => "trend",
+            EntryFilter::Adx(_, _) => "adx",
+            EntryFilter::Rsi(_, _, _) => "rsi",
+            EntryFilter::Volatility(_, _, _) => "volatility",
+            EntryFilter::Bollinger(_) => "bollinger",
+            EntryFilter::Donchian(_, _) => "donchian",
+            EntryFilter::Volume(_, _) => "volume",
+        }
+    }
+}
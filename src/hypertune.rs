@@ -1,97 +1,654 @@
-use std::{fs::File, path::Path};
+use std::{cmp::Ordering, fs::File, path::Path, time::Instant};
 
-use log::info;
+use log::{info, warn};
 use serde_json::{json, Map, Value};
 use trade_utils::types::kline::Kline;
 
-use crate::{backtest, types::BacktestConfig};
+use crate::{
+    analysis::BasketObjective,
+    backtest::{self, BacktestSummary},
+    cache::{cache_key, data_hash, ResultCache},
+    config::{from_value, InvalidField, Validate},
+    types::BacktestConfig,
+};
 
-pub fn hypertune(value: &Value, klines: &Vec<Kline>, symbol: String) {
-    let raw_config = value.as_object().unwrap();
+/// Backtest of one config of the hypertune grid.
+#[derive(Debug, Clone)]
+pub struct HypertuneResult {
+    pub config: BacktestConfig,
+    pub metrics: BacktestSummary,
+}
+
+/// Klines of one symbol of a basket hypertune, the symbols share the same regime klines.
+#[derive(Debug, Clone)]
+pub struct SymbolKlines {
+    pub symbol: String,
+    pub klines: Vec<Kline>,
+    pub regime_klines: Vec<Kline>,
+}
+
+/// Values of a tuned field, the objects of the hypertune config.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TuneParam {
+    // {min, max, step} with any float, `inclusive` (default true) takes max when on the grid
+    Range {
+        min: f64,
+        max: f64,
+        step: f64,
+        inclusive: bool,
+    },
+    // {min, max, step} of integers only
+    IntRange {
+        min: i64,
+        max: i64,
+        step: i64,
+        inclusive: bool,
+    },
+    Values(Vec<Value>), // {values: [...]}, explicit values or categorical options
+}
+
+const MAX_DECIMALS: i32 = 10;
+// Best configs of a basket hypertune in the logs
+const BASKET_TOP: usize = 5;
+
+/// Fewest decimals that write the number, e.g. 3 for 0.056.
+fn decimals(value: f64) -> i32 {
+    (0..MAX_DECIMALS)
+        .find(|decimals| {
+            let scaled = value * 10f64.powi(*decimals);
+            (scaled - scaled.round()).abs() < 1e-9 * scaled.abs().max(1.)
+        })
+        .unwrap_or(MAX_DECIMALS)
+}
+
+/// The `index`-th value is `min + index * step`, so the values don't drift with the step.
+fn grid(min: i64, max: i64, step: i64, inclusive: bool) -> impl Iterator<Item = i64> {
+    let count = match (inclusive, max.cmp(&min)) {
+        (true, Ordering::Less) | (false, Ordering::Less | Ordering::Equal) => 0,
+        (true, _) => (max - min) / step + 1,
+        (false, _) => (max - min + step - 1) / step,
+    };
+    (0..count).map(move |index| min + index * step)
+}
+
+impl TuneParam {
+    pub fn parse(field: &str, value: &Value) -> Result<TuneParam, InvalidField> {
+        let invalid = |reason: &str| InvalidField {
+            field: field.to_owned(),
+            reason: reason.to_owned(),
+        };
+        if let Some(values) = value.get("values") {
+            return match values.as_array() {
+                Some(values) if !values.is_empty() => Ok(TuneParam::Values(values.clone())),
+                _ => Err(invalid("values must be a non-empty list")),
+            };
+        }
+        let (min, max, step) = match (value.get("min"), value.get("max"), value.get("step")) {
+            (Some(min), Some(max), Some(step)) => (min, max, step),
+            _ => return Err(invalid("must be {min, max, step} or {values: [...]}")),
+        };
+        let inclusive = match value.get("inclusive") {
+            Some(inclusive) => inclusive
+                .as_bool()
+                .ok_or_else(|| invalid("inclusive must be true or false"))?,
+            None => true,
+        };
+        if let (Some(min), Some(max), Some(step)) = (min.as_i64(), max.as_i64(), step.as_i64()) {
+            if step <= 0 {
+                return Err(invalid("step must be > 0"));
+            }
+            return Ok(TuneParam::IntRange {
+                min,
+                max,
+                step,
+                inclusive,
+            });
+        }
+        match (min.as_f64(), max.as_f64(), step.as_f64()) {
+            (Some(_), Some(_), Some(step)) if step <= 0. => Err(invalid("step must be > 0")),
+            (Some(min), Some(max), Some(step)) => Ok(TuneParam::Range {
+                min,
+                max,
+                step,
+                inclusive,
+            }),
+            _ => Err(invalid("min, max and step must be numbers")),
+        }
+    }
+
+    pub fn values(&self) -> Vec<Value> {
+        match self {
+            TuneParam::Range {
+                min,
+                max,
+                step,
+                inclusive,
+            } => {
+                // On a grid of integers scaled by the decimals of the spec, e.g. 0.056 is 56
+                let scale = 10f64.powi(decimals(*min).max(decimals(*max)).max(decimals(*step)));
+                let scaled = |value: f64| (value * scale).round() as i64;
+                grid(scaled(*min), scaled(*max), scaled(*step), *inclusive)
+                    .map(|value| json!(value as f64 / scale))
+                    .collect()
+            }
+            TuneParam::IntRange {
+                min,
+                max,
+                step,
+                inclusive,
+            } => grid(*min, *max, *step, *inclusive)
+                .map(|value| json!(value))
+                .collect(),
+            TuneParam::Values(values) => values.clone(),
+        }
+    }
+}
+
+pub(crate) fn config_object(value: &Value) -> Result<&Map<String, Value>, InvalidField> {
+    value.as_object().ok_or_else(|| InvalidField {
+        field: "hypertune_config".to_owned(),
+        reason: "should be an object".to_owned(),
+    })
+}
+
+/// Tuned fields of the hypertune config with their values.
+pub fn tune_params(value: &Value) -> Result<Vec<(String, TuneParam)>, InvalidField> {
+    config_object(value)?
+        .iter()
+        .filter(|(_, field_value)| field_value.is_object())
+        .map(|(field, field_value)| Ok((field.clone(), TuneParam::parse(field, field_value)?)))
+        .collect()
+}
+
+/// Expands the tuned fields of the hypertune config into the valid backtest configs, with the
+/// names of the tuned fields.
+pub fn hypertune_configs(
+    value: &Value,
+) -> Result<(Vec<BacktestConfig>, Vec<String>), InvalidField> {
+    let raw_config = config_object(value)?;
     let mut backtest_configs: Vec<BacktestConfig> = Vec::new();
     let mut backtest_config_value = json!({});
-    let mut tune_fields = Vec::new();
     raw_config.iter().for_each(|(k, v)| {
-        println!("k: {} v: {}", k, v);
-        if v.as_object().is_some() {
-            tune_fields.push(k);
-        } else {
+        if !v.is_object() {
             backtest_config_value[k] = v.clone();
         }
     });
+    let params = tune_params(value)?;
+    let tune_values: Vec<(String, Vec<Value>)> = params
+        .iter()
+        .map(|(field, param)| (field.clone(), param.values()))
+        .collect();
+    for (field, values) in tune_values.iter() {
+        let values_str: Vec<String> = values.iter().map(Value::to_string).collect();
+        info!(
+            "{}: {} values [{}]",
+            field,
+            values.len(),
+            values_str.join(", ")
+        );
+    }
+    let combinations: usize = tune_values.iter().map(|(_, values)| values.len()).product();
     parse_backtest_configs(
-        raw_config,
+        &tune_values,
         &mut backtest_config_value,
         &mut backtest_configs,
-        &mut tune_fields,
         0,
     );
+    let tune_fields: Vec<String> = params.into_iter().map(|(field, _)| field).collect();
     info!("tune_fields: {:?}", tune_fields);
+    info!(
+        "{} combinations, {} valid configs",
+        combinations,
+        backtest_configs.len()
+    );
+    Ok((backtest_configs, tune_fields))
+}
+
+pub fn hypertune_config(
+    config: &BacktestConfig,
+    klines: &[Kline],
+    regime_klines: &[Kline],
+    symbol: &str,
+) -> HypertuneResult {
+    let mut backtest = backtest::Backtest::new(config, false);
+    backtest.set_regime_klines(regime_klines.to_vec());
+    let metric = backtest.run(klines, symbol.to_string());
+    HypertuneResult {
+        config: config.clone(),
+        metrics: metric.summary(),
+    }
+}
+
+/// Backtests every config of the hypertune config without writing any output.
+pub fn run_hypertune(
+    value: &Value,
+    klines: &[Kline],
+    regime_klines: &[Kline],
+    symbol: &str,
+) -> Result<(Vec<HypertuneResult>, Vec<String>), InvalidField> {
+    let (backtest_configs, tune_fields) = hypertune_configs(value)?;
+    let results = backtest_configs
+        .iter()
+        .map(|config| hypertune_config(config, klines, regime_klines, symbol))
+        .collect();
+    Ok((results, tune_fields))
+}
+
+/// Backtests every config into hypertune_output.csv, `dry_run` stops after the runtime estimate.
+/// Results are taken from and added to the cache at `cache_path`.
+pub fn hypertune(
+    value: &Value,
+    klines: &Vec<Kline>,
+    regime_klines: &Vec<Kline>,
+    symbol: String,
+    dry_run: bool,
+    cache_path: Option<&Path>,
+) -> Result<(), InvalidField> {
+    let (backtest_configs, tune_fields) = hypertune_configs(value)?;
+    let mut cache = open_cache(cache_path);
+    let data_hash = data_hash(&symbol, klines, regime_klines);
+    let keys: Vec<String> = backtest_configs
+        .iter()
+        .map(|config| cache_key(config, &data_hash))
+        .collect();
+    let uncached: Vec<usize> = (0..backtest_configs.len())
+        .filter(|index| match &cache {
+            Some(cache) => cache.get(&keys[*index]).is_none(),
+            None => true,
+        })
+        .collect();
+    info!(
+        "{} configs, {} cached, {} to backtest",
+        backtest_configs.len(),
+        backtest_configs.len() - uncached.len(),
+        uncached.len()
+    );
+
+    // The runtime of the first config to backtest times the configs to backtest
+    let mut first_result = None;
+    if let Some(first_index) = uncached.first() {
+        let started = Instant::now();
+        let metrics = hypertune_config(
+            &backtest_configs[*first_index],
+            klines,
+            regime_klines,
+            &symbol,
+        )
+        .metrics;
+        let estimate = started.elapsed().as_secs_f64() * uncached.len() as f64;
+        info!(
+            "Estimated runtime of {} configs: {:.1}s ({:.1} hours)",
+            uncached.len(),
+            estimate,
+            estimate / 3600.
+        );
+        // A dry run leaves the cache as it was
+        if let (Some(cache), false) = (cache.as_mut(), dry_run) {
+            cache_result(
+                cache,
+                keys[*first_index].clone(),
+                &backtest_configs[*first_index],
+                &metrics,
+            );
+        }
+        first_result = Some((*first_index, metrics));
+    }
+    if dry_run {
+        return Ok(());
+    }
     let output_path = Path::new("hypertune_output.csv");
     let file = File::create(output_path).unwrap();
     let mut writer = csv::Writer::from_writer(file);
-    writer
-        .write_record(&[
-            "initial_captial",
-            "usd_balance",
-            "max_usd",
-            "min_usd",
-            "win",
-            "lose",
-            "win_rate",
-            "total_fee",
+    let fixed_fields = ["risk_portion", "tp_ratio", "look_back_count"];
+    // Fields like the stop management rules are only in the output when tuned
+    let extra_fields: Vec<&String> = tune_fields
+        .iter()
+        .filter(|field| !fixed_fields.contains(&field.as_str()))
+        .collect();
+    let mut header = vec![
+        "initial_captial",
+        "usd_balance",
+        "max_usd",
+        "min_usd",
+        "win",
+        "lose",
+        "win_rate",
+        "total_fee",
+        "total_profit",
+        "risk_portion",
+        "tp_ratio",
+        "look_back_count",
+        "long_win_rate",
+        "long_total_profit",
+        "long_max_drawdown",
+        "short_win_rate",
+        "short_total_profit",
+        "short_max_drawdown",
+        "max_drawdown",
+        "max_drawdown_ratio",
+    ];
+    header.extend(extra_fields.iter().map(|field| field.as_str()));
+    writer.write_record(&header).unwrap();
+    backtest_configs
+        .iter()
+        .enumerate()
+        .for_each(|(index, config)| {
+            let cached = cache
+                .as_ref()
+                .and_then(|cache| cache.get(&keys[index]).cloned());
+            let metric = match (&first_result, cached) {
+                (Some((first_index, metrics)), _) if *first_index == index => metrics.clone(),
+                (_, Some(metrics)) => metrics,
+                _ => {
+                    let metrics = hypertune_config(config, klines, regime_klines, &symbol).metrics;
+                    if let Some(cache) = cache.as_mut() {
+                        cache_result(cache, keys[index].clone(), config, &metrics);
+                    }
+                    metrics
+                }
+            };
+            let mut record = Vec::new();
+            record.push(metric.initial_captial.to_string());
+            record.push(metric.usd_balance.to_string());
+            record.push(metric.max_usd.to_string());
+            record.push(metric.min_usd.to_string());
+            record.push(metric.win.to_string());
+            record.push(metric.lose.to_string());
+            record.push((metric.win as f64 / (metric.win + metric.lose) as f64).to_string());
+            record.push(metric.total_fee.to_string());
+            record.push(metric.total_profit.to_string());
+            record.push(config.risk_portion.to_string());
+            record.push(config.tp_ratio.to_string());
+            record.push(config.look_back_count.to_string());
+            for side in [&metric.long, &metric.short] {
+                record.push(side.win_rate().to_string());
+                record.push(side.total_profit.to_string());
+                record.push(side.max_drawdown.to_string());
+            }
+            record.push(metric.max_drawdown.to_string());
+            record.push(metric.max_drawdown_ratio.to_string());
+            let config_value = serde_json::to_value(config).unwrap();
+            for field in extra_fields.iter() {
+                record.push(config_value[field.as_str()].to_string());
+            }
+            writer.write_record(&record).unwrap();
+            writer.flush().unwrap();
+        });
+    Ok(())
+}
+
+// Hypertune goes on without the cache when it can't be opened
+fn open_cache(cache_path: Option<&Path>) -> Option<ResultCache> {
+    let path = cache_path?;
+    match ResultCache::open(path) {
+        Ok(cache) => Some(cache),
+        Err(err) => {
+            warn!("Open cache {:?} error, run without it, {}", path, err);
+            None
+        }
+    }
+}
+
+// A lost cache line only costs a backtest on the next run
+fn cache_result(
+    cache: &mut ResultCache,
+    key: String,
+    config: &BacktestConfig,
+    metrics: &BacktestSummary,
+) {
+    if let Err(err) = cache.insert(key, config, metrics) {
+        warn!("Write cache error, {}", err);
+    }
+}
+
+// Metrics of the config from the cache, backtested and cached when missing
+fn cached_metrics(
+    cache: &mut Option<ResultCache>,
+    key: &str,
+    config: &BacktestConfig,
+    symbol_klines: &SymbolKlines,
+) -> BacktestSummary {
+    if let Some(metrics) = cache.as_ref().and_then(|cache| cache.get(key)) {
+        return metrics.clone();
+    }
+    let metrics = hypertune_config(
+        config,
+        &symbol_klines.klines,
+        &symbol_klines.regime_klines,
+        &symbol_klines.symbol,
+    )
+    .metrics;
+    if let Some(cache) = cache.as_mut() {
+        cache_result(cache, key.to_owned(), config, &metrics);
+    }
+    metrics
+}
+
+/// Backtests every config on every symbol of the basket into basket_hypertune_output.csv, with
+/// the combined `objective` and the metrics of each symbol. The best configs by the objective
+/// are logged, `dry_run` stops after the runtime estimate.
+pub fn basket_hypertune(
+    value: &Value,
+    basket: &[SymbolKlines],
+    objective: BasketObjective,
+    dry_run: bool,
+    cache_path: Option<&Path>,
+) -> Result<(), InvalidField> {
+    let (backtest_configs, tune_fields) = hypertune_configs(value)?;
+    let mut cache = open_cache(cache_path);
+    let data_hashes: Vec<String> = basket
+        .iter()
+        .map(|symbol_klines| {
+            data_hash(
+                &symbol_klines.symbol,
+                &symbol_klines.klines,
+                &symbol_klines.regime_klines,
+            )
+        })
+        .collect();
+    // keys[config][symbol]
+    let keys: Vec<Vec<String>> = backtest_configs
+        .iter()
+        .map(|config| {
+            data_hashes
+                .iter()
+                .map(|data_hash| cache_key(config, data_hash))
+                .collect()
+        })
+        .collect();
+    let uncached = keys
+        .iter()
+        .flatten()
+        .filter(|key| match &cache {
+            Some(cache) => cache.get(key).is_none(),
+            None => true,
+        })
+        .count();
+    info!(
+        "{} configs on {} symbols, {} backtests, {} cached, {} to backtest",
+        backtest_configs.len(),
+        basket.len(),
+        backtest_configs.len() * basket.len(),
+        backtest_configs.len() * basket.len() - uncached,
+        uncached
+    );
+    if dry_run {
+        // The runtime of the first config on the basket, per backtest
+        if let Some(config) = backtest_configs.first() {
+            let started = Instant::now();
+            for symbol_klines in basket {
+                hypertune_config(
+                    config,
+                    &symbol_klines.klines,
+                    &symbol_klines.regime_klines,
+                    &symbol_klines.symbol,
+                );
+            }
+            let estimate = started.elapsed().as_secs_f64() / basket.len() as f64 * uncached as f64;
+            info!(
+                "Estimated runtime of {} backtests: {:.1}s ({:.1} hours)",
+                uncached,
+                estimate,
+                estimate / 3600.
+            );
+        }
+        return Ok(());
+    }
+
+    let output_path = Path::new("basket_hypertune_output.csv");
+    let file = File::create(output_path).unwrap();
+    let mut writer = csv::Writer::from_writer(file);
+    let mut header: Vec<String> = tune_fields.clone();
+    header.push("objective".to_string());
+    for symbol_klines in basket {
+        for column in [
             "total_profit",
-            "risk_portion",
-            "tp_ratio",
-            "look_back_count",
-        ])
-        .unwrap();
-    backtest_configs.iter().for_each(|config| {
-        let mut backtest = backtest::Backtest::new(config, false);
-        let metric = backtest.run(klines, symbol.clone());
-        let mut record = Vec::new();
-        record.push(metric.initial_captial.to_string());
-        record.push(metric.usd_balance.to_string());
-        record.push(metric.max_usd.to_string());
-        record.push(metric.min_usd.to_string());
-        record.push(metric.win.to_string());
-        record.push(metric.lose.to_string());
-        record.push((metric.win as f64 / (metric.win + metric.lose) as f64).to_string());
-        record.push(metric.total_fee.to_string());
-        record.push(metric.total_profit.to_string());
-        record.push(config.risk_portion.to_string());
-        record.push(config.tp_ratio.to_string());
-        record.push(config.look_back_count.to_string());
+            "win_rate",
+            "max_drawdown",
+            "max_drawdown_ratio",
+            "sharpe_ratio",
+        ] {
+            header.push(format!("{}_{}", symbol_klines.symbol, column));
+        }
+    }
+    writer.write_record(&header).unwrap();
+    let mut objectives: Vec<(usize, f64)> = Vec::new();
+    for (index, config) in backtest_configs.iter().enumerate() {
+        let metrics: Vec<BacktestSummary> = basket
+            .iter()
+            .zip(keys[index].iter())
+            .map(|(symbol_klines, key)| cached_metrics(&mut cache, key, config, symbol_klines))
+            .collect();
+        let objective_value = objective.value(&metrics);
+        let config_value = serde_json::to_value(config).unwrap();
+        let mut record: Vec<String> = tune_fields
+            .iter()
+            .map(|field| config_value[field.as_str()].to_string())
+            .collect();
+        record.push(objective_value.to_string());
+        for metric in metrics.iter() {
+            record.push(metric.total_profit.to_string());
+            record.push(metric.win_rate.to_string());
+            record.push(metric.max_drawdown.to_string());
+            record.push(metric.max_drawdown_ratio.to_string());
+            record.push(metric.sharpe_ratio.to_string());
+        }
         writer.write_record(&record).unwrap();
         writer.flush().unwrap();
-    });
+        objectives.push((index, objective_value));
+    }
+
+    objectives.sort_by(|a, b| b.1.total_cmp(&a.1));
+    for (rank, (index, objective_value)) in objectives.iter().take(BASKET_TOP).enumerate() {
+        let config_value = serde_json::to_value(&backtest_configs[*index]).unwrap();
+        let params: Vec<String> = tune_fields
+            .iter()
+            .map(|field| format!("{}: {}", field, config_value[field.as_str()]))
+            .collect();
+        info!(
+            "#{} {:?} {:.4}, {}",
+            rank + 1,
+            objective,
+            objective_value,
+            params.join(", ")
+        );
+    }
+    Ok(())
 }
+
 pub fn parse_backtest_configs(
-    raw_config: &Map<String, Value>,
+    tune_values: &[(String, Vec<Value>)],
     backtest_config_value: &mut Value,
     backtest_configs: &mut Vec<BacktestConfig>,
-    tune_fields: &Vec<&String>,
     index: usize,
 ) {
-    if index == tune_fields.len() {
+    if index == tune_values.len() {
+        // e.g. a float value of an integer field
         let backtest_config: BacktestConfig =
-            serde_json::from_value(backtest_config_value.clone()).unwrap();
-        backtest_configs.push(backtest_config);
+            match from_value(Path::new("hypertune"), backtest_config_value.clone()) {
+                Ok(backtest_config) => backtest_config,
+                Err(err) => {
+                    warn!("Skip config, {}", err);
+                    return;
+                }
+            };
+        match backtest_config.validate() {
+            Ok(()) => backtest_configs.push(backtest_config),
+            Err(invalid) => warn!(
+                "Skip config with field `{}` {}",
+                invalid.field, invalid.reason
+            ),
+        }
         return;
     }
-    let field_name = tune_fields[index];
-    let mut min = raw_config[field_name]["min"].as_f64().unwrap();
-    let max = raw_config[field_name]["max"].as_f64().unwrap();
-    let step = raw_config[field_name]["step"].as_f64().unwrap();
-    while min <= max {
-        backtest_config_value[field_name] = json!(min);
+    let (field_name, values) = &tune_values[index];
+    for value in values {
+        backtest_config_value[field_name] = value.clone();
         parse_backtest_configs(
-            raw_config,
+            tune_values,
             backtest_config_value,
             backtest_configs,
-            tune_fields,
             index + 1,
         );
-        min += step;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(spec: Value) -> Vec<Value> {
+        TuneParam::parse("field", &spec).unwrap().values()
+    }
+
+    fn values_of_ints(spec: Value) -> Vec<i64> {
+        values(spec)
+            .iter()
+            .map(|value| value.as_i64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn float_range_has_no_drift_and_takes_max() {
+        let values = values(json!({"min": 0.01, "max": 0.056, "step": 0.001}));
+        assert_eq!(values.len(), 47);
+        for (index, value) in values.iter().enumerate() {
+            let expected = format!("{:.3}", 0.01 + index as f64 * 0.001);
+            assert_eq!(value.to_string(), expected.trim_end_matches('0'));
+        }
+        assert_eq!(values.last(), Some(&json!(0.056)));
+    }
+
+    #[test]
+    fn exclusive_range_stops_before_max() {
+        let values = values(json!({"min": 1.0, "max": 2.0, "step": 0.25, "inclusive": false}));
+        assert_eq!(
+            values,
+            vec![json!(1.0), json!(1.25), json!(1.5), json!(1.75)]
+        );
+        let values = values_of_ints(json!({"min": 2, "max": 8, "step": 3, "inclusive": false}));
+        assert_eq!(values, vec![2, 5]);
+    }
+
+    #[test]
+    fn int_range_keeps_integers() {
+        let spec = json!({"min": 10, "max": 20, "step": 4});
+        assert!(matches!(
+            TuneParam::parse("atr_period", &spec),
+            Ok(TuneParam::IntRange { .. })
+        ));
+        assert_eq!(values_of_ints(spec), vec![10, 14, 18]);
+        assert_eq!(
+            values_of_ints(json!({"min": 10, "max": 20, "step": 5})),
+            vec![10, 15, 20]
+        );
+    }
+
+    #[test]
+    fn invalid_spec_names_the_field() {
+        let invalid =
+            TuneParam::parse("tp_ratio", &json!({"min": 1, "max": 2, "step": 0})).unwrap_err();
+        assert_eq!(invalid.field, "tp_ratio");
+        assert!(hypertune_configs(&json!([])).is_err());
     }
 }
@@ -0,0 +1,552 @@
+use std::collections::VecDeque;
+
+use trade_utils::types::kline::Kline;
+
+/// Streaming indicator fed with closed klines one at a time, backtest and live trade feed it
+/// the same way so both see identical values. `update` returns None while warming up.
+pub trait Indicator {
+    type Output;
+
+    fn update(&mut self, kline: &Kline) -> Option<Self::Output>;
+    fn value(&self) -> Option<Self::Output>;
+}
+
+/// Simple moving average of the close.
+pub struct Sma {
+    period: usize,
+    window: VecDeque<f64>,
+    sum: f64,
+}
+
+impl Sma {
+    pub fn new(period: usize) -> Sma {
+        Sma {
+            period: period.max(1),
+            window: VecDeque::new(),
+            sum: 0.,
+        }
+    }
+
+    pub fn update_value(&mut self, value: f64) -> Option<f64> {
+        self.window.push_back(value);
+        self.sum += value;
+        if self.window.len() > self.period {
+            self.sum -= self.window.pop_front().unwrap();
+        }
+        self.current()
+    }
+
+    fn current(&self) -> Option<f64> {
+        if self.window.len() == self.period {
+            Some(self.sum / self.period as f64)
+        } else {
+            None
+        }
+    }
+}
+
+impl Indicator for Sma {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.update_value(kline.close)
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.current()
+    }
+}
+
+/// Exponential moving average of the close, seeded with the SMA of the first `period` values.
+pub struct Ema {
+    period: usize,
+    count: usize,
+    seed_sum: f64,
+    value: Option<f64>,
+}
+
+impl Ema {
+    pub fn new(period: usize) -> Ema {
+        Ema {
+            period: period.max(1),
+            count: 0,
+            seed_sum: 0.,
+            value: None,
+        }
+    }
+
+    pub fn period(&self) -> usize {
+        self.period
+    }
+
+    pub fn update_value(&mut self, value: f64) -> Option<f64> {
+        self.value = match self.value {
+            Some(ema) => {
+                let alpha = 2. / (self.period as f64 + 1.);
+                Some(ema + alpha * (value - ema))
+            }
+            None => {
+                self.count += 1;
+                self.seed_sum += value;
+                if self.count == self.period {
+                    Some(self.seed_sum / self.period as f64)
+                } else {
+                    None
+                }
+            }
+        };
+        self.value
+    }
+}
+
+impl Indicator for Ema {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.update_value(kline.close)
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.value
+    }
+}
+
+/// Average true range with Wilder's smoothing, the first `period` values are averaged.
+pub struct Atr {
+    period: usize,
+    count: usize,
+    prev_close: Option<f64>,
+    value: f64,
+}
+
+impl Atr {
+    pub fn new(period: usize) -> Atr {
+        Atr {
+            period: period.max(1),
+            count: 0,
+            prev_close: None,
+            value: 0.,
+        }
+    }
+}
+
+impl Indicator for Atr {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        let true_range = true_range(kline, self.prev_close);
+        self.prev_close = Some(kline.close);
+        self.count += 1;
+        let smoothing = self.count.min(self.period) as f64;
+        self.value += (true_range - self.value) / smoothing;
+        self.value()
+    }
+
+    fn value(&self) -> Option<f64> {
+        if self.count >= self.period {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+}
+
+fn true_range(kline: &Kline, prev_close: Option<f64>) -> f64 {
+    match prev_close {
+        Some(prev_close) => (kline.high - kline.low)
+            .max((kline.high - prev_close).abs())
+            .max((kline.low - prev_close).abs()),
+        None => kline.high - kline.low,
+    }
+}
+
+/// Relative strength index with Wilder's smoothing, in [0, 100].
+pub struct Rsi {
+    period: usize,
+    count: usize,
+    prev_close: Option<f64>,
+    avg_gain: f64,
+    avg_loss: f64,
+}
+
+impl Rsi {
+    pub fn new(period: usize) -> Rsi {
+        Rsi {
+            period: period.max(1),
+            count: 0,
+            prev_close: None,
+            avg_gain: 0.,
+            avg_loss: 0.,
+        }
+    }
+}
+
+impl Indicator for Rsi {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        if let Some(prev_close) = self.prev_close {
+            let change = kline.close - prev_close;
+            self.count += 1;
+            let smoothing = self.count.min(self.period) as f64;
+            self.avg_gain += (change.max(0.) - self.avg_gain) / smoothing;
+            self.avg_loss += ((-change).max(0.) - self.avg_loss) / smoothing;
+        }
+        self.prev_close = Some(kline.close);
+        self.value()
+    }
+
+    fn value(&self) -> Option<f64> {
+        if self.count < self.period {
+            None
+        } else if self.avg_loss == 0. {
+            Some(100.)
+        } else {
+            Some(100. - 100. / (1. + self.avg_gain / self.avg_loss))
+        }
+    }
+}
+
+/// Average directional index with Wilder's smoothing, in [0, 100].
+pub struct Adx {
+    period: usize,
+    count: usize,
+    prev_kline: Option<Kline>,
+    true_range: f64,
+    plus_dm: f64,
+    minus_dm: f64,
+    dx_count: usize,
+    value: f64,
+}
+
+impl Adx {
+    pub fn new(period: usize) -> Adx {
+        Adx {
+            period: period.max(1),
+            count: 0,
+            prev_kline: None,
+            true_range: 0.,
+            plus_dm: 0.,
+            minus_dm: 0.,
+            dx_count: 0,
+            value: 0.,
+        }
+    }
+}
+
+impl Indicator for Adx {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        if let Some(prev_kline) = &self.prev_kline {
+            let up_move = kline.high - prev_kline.high;
+            let down_move = prev_kline.low - kline.low;
+            let plus_dm = if up_move > down_move && up_move > 0. {
+                up_move
+            } else {
+                0.
+            };
+            let minus_dm = if down_move > up_move && down_move > 0. {
+                down_move
+            } else {
+                0.
+            };
+            self.count += 1;
+            let smoothing = self.count.min(self.period) as f64;
+            self.true_range +=
+                (true_range(kline, Some(prev_kline.close)) - self.true_range) / smoothing;
+            self.plus_dm += (plus_dm - self.plus_dm) / smoothing;
+            self.minus_dm += (minus_dm - self.minus_dm) / smoothing;
+
+            if self.count >= self.period && self.true_range > 0. {
+                let plus_di = 100. * self.plus_dm / self.true_range;
+                let minus_di = 100. * self.minus_dm / self.true_range;
+                let dx = if plus_di + minus_di > 0. {
+                    100. * (plus_di - minus_di).abs() / (plus_di + minus_di)
+                } else {
+                    0.
+                };
+                self.dx_count += 1;
+                self.value += (dx - self.value) / self.dx_count.min(self.period) as f64;
+            }
+        }
+        self.prev_kline = Some(kline.clone());
+        self.value()
+    }
+
+    fn value(&self) -> Option<f64> {
+        if self.dx_count >= self.period {
+            Some(self.value)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BollingerBands {
+    pub upper: f64,
+    pub middle: f64,
+    pub lower: f64,
+}
+
+/// SMA of the close with bands `std_dev` population standard deviations away.
+pub struct Bollinger {
+    period: usize,
+    std_dev: f64,
+    window: VecDeque<f64>,
+}
+
+impl Bollinger {
+    pub fn new(period: usize, std_dev: f64) -> Bollinger {
+        Bollinger {
+            period: period.max(1),
+            std_dev,
+            window: VecDeque::new(),
+        }
+    }
+}
+
+impl Indicator for Bollinger {
+    type Output = BollingerBands;
+
+    fn update(&mut self, kline: &Kline) -> Option<BollingerBands> {
+        self.window.push_back(kline.close);
+        if self.window.len() > self.period {
+            self.window.pop_front();
+        }
+        self.value()
+    }
+
+    fn value(&self) -> Option<BollingerBands> {
+        if self.window.len() < self.period {
+            return None;
+        }
+        let middle = self.window.iter().sum::<f64>() / self.period as f64;
+        let variance = self
+            .window
+            .iter()
+            .map(|close| (close - middle).powi(2))
+            .sum::<f64>()
+            / self.period as f64;
+        let band = self.std_dev * variance.sqrt();
+        Some(BollingerBands {
+            upper: middle + band,
+            middle,
+            lower: middle - band,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DonchianChannel {
+    pub upper: f64,
+    pub lower: f64,
+}
+
+/// Highest high and lowest low of the last `period` klines.
+pub struct Donchian {
+    period: usize,
+    highs: VecDeque<f64>,
+    lows: VecDeque<f64>,
+}
+
+impl Donchian {
+    pub fn new(period: usize) -> Donchian {
+        Donchian {
+            period: period.max(1),
+            highs: VecDeque::new(),
+            lows: VecDeque::new(),
+        }
+    }
+}
+
+impl Indicator for Donchian {
+    type Output = DonchianChannel;
+
+    fn update(&mut self, kline: &Kline) -> Option<DonchianChannel> {
+        self.highs.push_back(kline.high);
+        self.lows.push_back(kline.low);
+        if self.highs.len() > self.period {
+            self.highs.pop_front();
+            self.lows.pop_front();
+        }
+        self.value()
+    }
+
+    fn value(&self) -> Option<DonchianChannel> {
+        if self.highs.len() < self.period {
+            return None;
+        }
+        Some(DonchianChannel {
+            upper: self.highs.iter().cloned().fold(f64::MIN, f64::max),
+            lower: self.lows.iter().cloned().fold(f64::MAX, f64::min),
+        })
+    }
+}
+
+/// Simple moving average of the volume.
+pub struct VolumeMa {
+    sma: Sma,
+}
+
+impl VolumeMa {
+    pub fn new(period: usize) -> VolumeMa {
+        VolumeMa {
+            sma: Sma::new(period),
+        }
+    }
+}
+
+impl Indicator for VolumeMa {
+    type Output = f64;
+
+    fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.sma.update_value(kline.volume)
+    }
+
+    fn value(&self) -> Option<f64> {
+        self.sma.current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kline(high: f64, low: f64, close: f64) -> Kline {
+        Kline {
+            open_timestamp: 0,
+            close_timestamp: 0,
+            open: close,
+            high,
+            low,
+            close,
+            volume: 0.,
+        }
+    }
+
+    fn closes(closes: &[f64]) -> Vec<Kline> {
+        closes
+            .iter()
+            .map(|&close| kline(close, close, close))
+            .collect()
+    }
+
+    fn assert_near(value: Option<f64>, expected: f64) {
+        let value = value.unwrap();
+        assert!((value - expected).abs() < 1e-9, "{} != {}", value, expected);
+    }
+
+    #[test]
+    fn sma_warms_up_then_rolls() {
+        let mut sma = Sma::new(3);
+        let values: Vec<_> = closes(&[1., 2., 3., 4.])
+            .iter()
+            .map(|kline| sma.update(kline))
+            .collect();
+        assert_eq!(values, vec![None, None, Some(2.), Some(3.)]);
+        assert_eq!(sma.value(), Some(3.));
+    }
+
+    #[test]
+    fn ema_is_seeded_with_the_sma() {
+        let mut ema = Ema::new(3);
+        let values: Vec<_> = closes(&[1., 2., 3., 4., 6.])
+            .iter()
+            .map(|kline| ema.update(kline))
+            .collect();
+        // Seed (1 + 2 + 3) / 3, then alpha = 2 / (3 + 1)
+        assert_eq!(values, vec![None, None, Some(2.), Some(3.), Some(4.5)]);
+    }
+
+    #[test]
+    fn atr_averages_the_first_period_then_smooths() {
+        let mut atr = Atr::new(3);
+        assert_eq!(atr.update(&kline(10., 8., 9.)), None);
+        assert_eq!(atr.update(&kline(11., 9., 10.)), None);
+        // True ranges 2, 2, 3
+        assert_near(atr.update(&kline(12., 9., 11.)), 7. / 3.);
+        // True range 4 from the previous close, 7 / 3 + (4 - 7 / 3) / 3
+        assert_near(atr.update(&kline(14., 10., 13.)), 26. / 9.);
+    }
+
+    #[test]
+    fn rsi_uses_wilder_smoothing() {
+        let mut rsi = Rsi::new(2);
+        let mut values = closes(&[10., 12., 11., 14.])
+            .into_iter()
+            .map(|kline| rsi.update(&kline));
+        assert_eq!(values.next(), Some(None));
+        assert_eq!(values.next(), Some(None));
+        // Average gain 1, average loss 0.5
+        assert_near(values.next().unwrap(), 100. - 100. / 3.);
+        // Average gain 2, average loss 0.25
+        assert_near(values.next().unwrap(), 100. - 100. / 9.);
+    }
+
+    #[test]
+    fn rsi_is_100_without_losses() {
+        let mut rsi = Rsi::new(2);
+        let values: Vec<_> = closes(&[1., 2., 3.])
+            .iter()
+            .map(|kline| rsi.update(kline))
+            .collect();
+        assert_eq!(values, vec![None, None, Some(100.)]);
+    }
+
+    #[test]
+    fn adx_warms_up_over_two_periods() {
+        let mut adx = Adx::new(2);
+        assert_eq!(adx.update(&kline(10., 8., 9.)), None);
+        assert_eq!(adx.update(&kline(12., 9., 11.)), None);
+        // +DI 50, -DI 0, DX 100
+        assert_eq!(adx.update(&kline(13., 10., 12.)), None);
+        // TR 3.5, +DM 0.75, -DM 1, DX 100 / 7, ADX (100 + 100 / 7) / 2
+        assert_near(adx.update(&kline(12., 8., 9.)), 400. / 7.);
+    }
+
+    #[test]
+    fn bollinger_uses_the_population_std_dev() {
+        let mut bollinger = Bollinger::new(3, 2.);
+        let klines = closes(&[1., 2., 3., 6.]);
+        assert!(bollinger.update(&klines[0]).is_none());
+        assert!(bollinger.update(&klines[1]).is_none());
+        let bands = bollinger.update(&klines[2]).unwrap();
+        let band = 2. * (2f64 / 3.).sqrt();
+        assert_near(Some(bands.middle), 2.);
+        assert_near(Some(bands.upper), 2. + band);
+        assert_near(Some(bands.lower), 2. - band);
+        assert_near(
+            bollinger.update(&klines[3]).map(|bands| bands.middle),
+            11. / 3.,
+        );
+    }
+
+    #[test]
+    fn donchian_tracks_the_window_extremes() {
+        let mut donchian = Donchian::new(2);
+        assert!(donchian.update(&kline(10., 8., 9.)).is_none());
+        let channel = donchian.update(&kline(12., 9., 11.)).unwrap();
+        assert_eq!((channel.upper, channel.lower), (12., 8.));
+        let channel = donchian.update(&kline(11., 5., 6.)).unwrap();
+        assert_eq!((channel.upper, channel.lower), (12., 5.));
+        let channel = donchian.update(&kline(7., 6., 6.)).unwrap();
+        assert_eq!((channel.upper, channel.lower), (11., 5.));
+    }
+
+    #[test]
+    fn volume_ma_averages_the_volume() {
+        let mut volume_ma = VolumeMa::new(2);
+        let values: Vec<_> = [100., 200., 600.]
+            .iter()
+            .map(|&volume| {
+                volume_ma.update(&Kline {
+                    volume,
+                    ..kline(1., 1., 1.)
+                })
+            })
+            .collect();
+        assert_eq!(values, vec![None, Some(150.), Some(400.)]);
+    }
+}
@@ -0,0 +1,379 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use trade_utils::types::kline::Kline;
+
+use crate::{
+    backtest::BacktestMetric,
+    config::ConfigError,
+    execution::LiveFill,
+    filter::EntryFilters,
+    indicators::{Atr, Indicator},
+    risk::{RiskConfig, RiskManager, RiskState},
+    signal::MomentumSignal,
+    strategy::{close_all_trades, open_trade, sl_tp_exit, ExitOutput},
+    types::{BacktestConfig, ManagedTrade},
+};
+
+pub const JOURNAL_DIR: &str = "./journal";
+
+/// Everything the strategy consumed and decided during a live session, in order.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Start {
+        symbol: String,
+        backtest_config: Box<BacktestConfig>,
+        risk_config: RiskConfig,
+        trades: Vec<ManagedTrade>, // Recovered from db
+    },
+    ReplayKline {
+        kline: Kline, // Warm-up kline before the session
+    },
+    RegimeKline {
+        kline: Kline,
+    },
+    Kline {
+        kline: Kline, // Closed kline, sl_tp_exit runs on it
+    },
+    Account {
+        usd_balance: f64, // open_trade runs on the last kline after it
+    },
+    Flatten {
+        kline: Kline, // Risk limit breached
+    },
+    Fill {
+        fill: LiveFill, // Limit entry of the decision below it
+    },
+    Decision {
+        close_timestamp: i64,
+        trades: Vec<ManagedTrade>,
+    },
+    StopOrders {
+        missing: Vec<i64>, // entry_ts of the trades whose mirrored stop could not be synced
+    },
+}
+
+pub struct Journal {
+    file: File,
+}
+
+impl Journal {
+    pub fn new(version: &str, start_ts: i64) -> Result<Journal, ConfigError> {
+        fs::create_dir_all(JOURNAL_DIR).map_err(|source| ConfigError::Io {
+            path: PathBuf::from(JOURNAL_DIR),
+            source,
+        })?;
+        Journal::open(Path::new(&format!(
+            "{}/{}_{}.jsonl",
+            JOURNAL_DIR, version, start_ts
+        )))
+    }
+
+    pub fn open(path: &Path) -> Result<Journal, ConfigError> {
+        info!("Journal to {:?}", path);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|source| ConfigError::Io {
+                path: path.to_owned(),
+                source,
+            })?;
+        Ok(Journal { file })
+    }
+
+    pub fn write(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let line = serde_json::to_string(entry)?;
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()
+    }
+}
+
+pub fn read_journal(path: &Path) -> Result<Vec<JournalEntry>, ConfigError> {
+    let io_error = |source| ConfigError::Io {
+        path: path.to_owned(),
+        source,
+    };
+    let file = File::open(path).map_err(io_error)?;
+    let mut entries = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.map_err(io_error)?;
+        if line.is_empty() {
+            continue;
+        }
+        // The last line is cut when the session was killed while writing it
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => warn!("Skip journal line of {:?}, {}", path, err),
+        }
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayResult {
+    pub decisions: usize,
+    pub mismatches: usize,
+}
+
+/// Feeds the journal through the strategy without an api client and checks every journaled
+/// decision is reproduced exactly.
+pub fn replay(entries: &[JournalEntry]) -> ReplayResult {
+    let mut result = ReplayResult::default();
+    let (symbol, config, risk_config, mut trades) = match entries.first() {
+        Some(JournalEntry::Start {
+            symbol,
+            backtest_config,
+            risk_config,
+            trades,
+        }) => (
+            symbol.clone(),
+            backtest_config.as_ref().clone(),
+            risk_config.clone(),
+            trades.clone(),
+        ),
+        _ => {
+            error!("Journal does not begin with a start entry");
+            return result;
+        }
+    };
+    // Trading was not halted while decisions were made, only the limits matter
+    let risk_manager = RiskManager::with_state(&risk_config, RiskState::default());
+    let mut signal = MomentumSignal::new(&config);
+    let mut filters = EntryFilters::new(&config);
+    let mut atr = Atr::new(config.atr_period);
+    let mut metric = BacktestMetric::new(&config);
+    let mut pending_kline: Option<Kline> = None;
+    // Replay places no orders and writes no trade log
+    let output = ExitOutput {
+        output_trade_log: false,
+        output_trade_log_name: "",
+        api_client_opt: None,
+    };
+
+    for entry in entries.iter().skip(1) {
+        match entry {
+            JournalEntry::Start { .. } => warn!("Ignore start entry in the middle of the journal"),
+            JournalEntry::ReplayKline { kline } => {
+                signal.update(kline);
+                filters.update(kline);
+                atr.update(kline);
+            }
+            JournalEntry::RegimeKline { kline } => filters.update_regime(kline),
+            JournalEntry::Kline { kline } => {
+                signal.update(kline);
+                filters.update(kline);
+                let atr_value = atr.update(kline);
+                sl_tp_exit(&mut metric, &config, &mut trades, kline, atr_value, output);
+                pending_kline = Some(kline.clone());
+            }
+            JournalEntry::Account { usd_balance } => metric.usd_balance = *usd_balance,
+            JournalEntry::Fill { fill } => metric.journal_fills.push_back(*fill),
+            JournalEntry::Flatten { kline } => {
+                close_all_trades(&mut metric, &config, &mut trades, kline, output);
+            }
+            JournalEntry::StopOrders { missing } => {
+                for trade in trades.iter_mut() {
+                    trade.stop_order_missing = missing.contains(&trade.entry_ts);
+                }
+            }
+            JournalEntry::Decision {
+                close_timestamp,
+                trades: journal_trades,
+            } => {
+                // open_trade of the account entry, after the journaled fills of its limit entries
+                if let Some(kline) = pending_kline.take() {
+                    open_trade(
+                        symbol.clone(),
+                        &mut metric,
+                        &config,
+                        &mut trades,
+                        signal.momentum(),
+                        &filters,
+                        false,
+                        "",
+                        &kline,
+                        Some(&risk_manager),
+                        None,
+                    );
+                }
+                metric.journal_fills.clear();
+                result.decisions += 1;
+                let replayed = serde_json::to_value(&trades).unwrap();
+                let journaled = serde_json::to_value(journal_trades).unwrap();
+                if replayed != journaled {
+                    result.mismatches += 1;
+                    error!(
+                        "Decision mismatch at {}, journal: {:?}, replay: {:?}",
+                        close_timestamp, journal_trades, trades
+                    );
+                } else {
+                    info!("Decision matched at {}", close_timestamp);
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+    const WARMUP: [f64; 4] = [100., 99., 98., 97.];
+    // Rises then falls, so the momentum crosses both ways
+    const CLOSES: [f64; 8] = [99., 102., 106., 104., 97., 92., 95., 101.];
+
+    fn config() -> BacktestConfig {
+        serde_json::from_value(json!({
+            "initial_captial": 1000.0,
+            "fee_rate": 0.0004,
+            "entry_portion": 0.5,
+            "look_back_count": 2,
+            "risk_portion": 0.05,
+            "tp_ratio": 2.0,
+            "candle_filter": false
+        }))
+        .unwrap()
+    }
+
+    fn kline(index: i64, close: f64) -> Kline {
+        Kline {
+            open_timestamp: index * DAY_MS,
+            close_timestamp: (index + 1) * DAY_MS - 1,
+            open: close,
+            high: close * 1.01,
+            low: close * 0.99,
+            close,
+            volume: 1.,
+        }
+    }
+
+    // Entries in the order live_trade writes them, with the decisions of the strategy
+    fn session(config: &BacktestConfig, warmup: &[f64], closes: &[f64]) -> Vec<JournalEntry> {
+        let risk_config = RiskConfig::default();
+        let mut entries = vec![JournalEntry::Start {
+            symbol: "BTCUSDT".to_owned(),
+            backtest_config: Box::new(config.clone()),
+            risk_config: risk_config.clone(),
+            trades: Vec::new(),
+        }];
+        let risk_manager = RiskManager::with_state(&risk_config, RiskState::default());
+        let mut signal = MomentumSignal::new(config);
+        let mut filters = EntryFilters::new(config);
+        let mut atr = Atr::new(config.atr_period);
+        let mut metric = BacktestMetric::new(config);
+        let mut trades = Vec::new();
+        let output = ExitOutput {
+            output_trade_log: false,
+            output_trade_log_name: "",
+            api_client_opt: None,
+        };
+        for (index, close) in warmup.iter().enumerate() {
+            let kline = kline(index as i64, *close);
+            signal.update(&kline);
+            filters.update(&kline);
+            atr.update(&kline);
+            entries.push(JournalEntry::ReplayKline { kline });
+        }
+        for (index, close) in closes.iter().enumerate() {
+            let kline = kline((warmup.len() + index) as i64, *close);
+            signal.update(&kline);
+            filters.update(&kline);
+            let atr_value = atr.update(&kline);
+            entries.push(JournalEntry::Kline {
+                kline: kline.clone(),
+            });
+            sl_tp_exit(&mut metric, config, &mut trades, &kline, atr_value, output);
+            entries.push(JournalEntry::Account {
+                usd_balance: metric.usd_balance,
+            });
+            open_trade(
+                "BTCUSDT".to_owned(),
+                &mut metric,
+                config,
+                &mut trades,
+                signal.momentum(),
+                &filters,
+                false,
+                "",
+                &kline,
+                Some(&risk_manager),
+                None,
+            );
+            entries.push(JournalEntry::Decision {
+                close_timestamp: kline.close_timestamp,
+                trades: trades.clone(),
+            });
+        }
+        entries
+    }
+
+    fn write_journal(path: &Path, entries: &[JournalEntry]) {
+        let mut journal = Journal::open(path).unwrap();
+        for entry in entries {
+            journal.write(entry).unwrap();
+        }
+    }
+
+    #[test]
+    fn replay_reproduces_a_written_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let config = config();
+        let entries = session(&config, &WARMUP, &CLOSES);
+        write_journal(&path, &entries);
+
+        let read = read_journal(&path).unwrap();
+        assert_eq!(read.len(), entries.len());
+        let result = replay(&read);
+        assert_eq!(result.decisions, 8);
+        assert_eq!(result.mismatches, 0);
+        // The session traded, so the decisions are not trivially empty
+        assert!(read.iter().any(|entry| matches!(
+            entry,
+            JournalEntry::Decision { trades, .. } if !trades.is_empty()
+        )));
+    }
+
+    #[test]
+    fn replay_reports_a_changed_decision() {
+        let config = config();
+        let mut entries = session(&config, &WARMUP, &CLOSES);
+        let decision = entries
+            .iter_mut()
+            .find_map(|entry| match entry {
+                JournalEntry::Decision { trades, .. } if !trades.is_empty() => Some(trades),
+                _ => None,
+            })
+            .unwrap();
+        decision.clear();
+        assert_eq!(replay(&entries).mismatches, 1);
+    }
+
+    #[test]
+    fn read_journal_skips_a_cut_last_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("killed.jsonl");
+        let config = config();
+        let entries = session(&config, &WARMUP, &CLOSES[..2]);
+        write_journal(&path, &entries);
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"event\":\"kline\",\"kline\":{{\"open_t").unwrap();
+
+        let read = read_journal(&path).unwrap();
+        assert_eq!(read.len(), entries.len());
+        assert_eq!(replay(&read).mismatches, 0);
+        assert!(matches!(
+            read_journal(&dir.path().join("missing.jsonl")),
+            Err(ConfigError::Io { .. })
+        ));
+    }
+}
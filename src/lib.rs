@@ -1,6 +1,24 @@
+pub mod analysis;
 pub mod backtest;
+pub mod cache;
+pub mod config;
 pub mod consts;
+pub mod divergence;
+pub mod execution;
+pub mod filter;
 pub mod hypertune;
+pub mod indicators;
+pub mod journal;
+pub mod live;
+pub mod monte_carlo;
+// Stop and limit orders, they need the order APIs of trade_utils
+#[cfg(feature = "exchange-orders")]
+pub mod orders;
+pub mod regime;
+pub mod report;
+pub mod risk;
+pub mod secrets;
+pub mod signal;
 pub mod strategy;
 pub mod types;
 pub mod utils;
@@ -0,0 +1,363 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::thread;
+
+use async_std::task;
+use chrono::Utc;
+use log::*;
+use trade_utils::types::timer::{FixedUpdate, Timer};
+
+#[cfg(feature = "exchange-orders")]
+use crate::orders::{sync_stop_orders, StopOrders};
+use crate::{
+    backtest::{unrealized_profit, BacktestMetric},
+    config::ConfigError,
+    filter::EntryFilters,
+    indicators::{Atr, Indicator},
+    journal::{Journal, JournalEntry},
+    risk::RiskManager,
+    secrets::{load_credentials, public_api_client},
+    signal::MomentumSignal,
+    strategy::{close_all_trades, open_trade, sl_tp_exit, ExitOutput},
+    types::{BacktestConfig, LiveSettingConfig},
+    utils::{get_trades, log_trades},
+};
+
+/// Trades the backtest config on the klines of the exchange. Paper trade places no orders and
+/// keeps its trades, journal and risk state apart from live trade under `{version}_paper`.
+pub fn live_trade(
+    setting_config: LiveSettingConfig,
+    backtest_config: BacktestConfig,
+    paper: bool,
+) -> Result<(), ConfigError> {
+    let mut setting_config = setting_config;
+    if paper {
+        setting_config.version = format!("{}_paper", setting_config.version);
+        setting_config.risk.state_path = format!("{}.paper", setting_config.risk.state_path);
+    }
+    let mut risk_manager = RiskManager::new(&setting_config.risk)?;
+    if risk_manager.is_halted() {
+        error!(
+            "Trading is halted by the risk manager: {:?}, remove {} to resume",
+            risk_manager.halt_reason(),
+            setting_config.risk.state_path
+        );
+        return Ok(());
+    }
+
+    let api_client = if paper {
+        public_api_client()
+    } else {
+        load_credentials(Path::new(&setting_config.secrets_path))?.api_client()
+    };
+    // Paper trade never places orders
+    let order_client = if paper { None } else { Some(&api_client) };
+    let symbol = setting_config.symbol;
+    if !paper {
+        let account =
+            task::block_on(api_client.get_account()).map_err(|err| ConfigError::Invalid {
+                path: setting_config.secrets_path.clone().into(),
+                field: "credentials".to_owned(),
+                reason: format!("get account error, {:?}", err),
+            })?;
+        info!("Current usd_balance: {}", account.get_usd_balance());
+    }
+
+    let version = setting_config.version;
+    let mut trades = task::block_on(get_trades(&version));
+    info!("Recover trades {:?} from db", trades);
+
+    // ===== Replay =====
+    let mut journal = Journal::new(&version, Utc::now().timestamp_millis())?;
+    write_journal(
+        &mut journal,
+        &JournalEntry::Start {
+            symbol: symbol.clone(),
+            backtest_config: Box::new(backtest_config.clone()),
+            risk_config: setting_config.risk.clone(),
+            trades: trades.clone(),
+        },
+    );
+    let mut signal = MomentumSignal::new(&backtest_config);
+    let mut filters = EntryFilters::new(&backtest_config);
+    let mut atr = Atr::new(backtest_config.atr_period);
+    // Replay enough klines to warm up the indicators
+    let replay_days = 30
+        .max(backtest_config.look_back_count + 1 + backtest_config.signal_smoothing)
+        .max(filters.warmup_bars())
+        .max(filters.regime_warmup_bars())
+        .max(backtest_config.atr_period)
+        + 2;
+    let start_time = (Utc::now() - chrono::Duration::try_days(replay_days as i64).unwrap())
+        .timestamp_millis()
+        .to_string();
+    let replay_klines_res =
+        task::block_on(api_client.get_klines(&symbol, "1d", Some(start_time.as_str()), None, None))
+            .unwrap();
+    let mut replay_klines = VecDeque::from(replay_klines_res);
+
+    // e.g. BTCUSDT_1d -> (BTCUSDT, 1d), the config validation checks the format
+    let regime_source = match &backtest_config.regime_collection {
+        Some(collection) => match collection.split_once('_') {
+            Some((regime_symbol, regime_interval)) => {
+                Some((regime_symbol.to_owned(), regime_interval.to_owned()))
+            }
+            None => {
+                error!("regime_collection {} is not symbol_interval", collection);
+                return Ok(());
+            }
+        },
+        None => None,
+    };
+    if let Some((regime_symbol, regime_interval)) = &regime_source {
+        let regime_klines = match task::block_on(api_client.get_klines(
+            regime_symbol,
+            regime_interval,
+            Some(start_time.as_str()),
+            None,
+            None,
+        )) {
+            Ok(regime_klines) => regime_klines,
+            Err(err) => {
+                warn!("Get regime klines error, {:?}", err);
+                Vec::new()
+            }
+        };
+        // The last regime kline is not closed yet
+        for kline in regime_klines
+            .iter()
+            .take(regime_klines.len().saturating_sub(1))
+        {
+            filters.update_regime(kline);
+            write_journal(
+                &mut journal,
+                &JournalEntry::RegimeKline {
+                    kline: kline.clone(),
+                },
+            );
+        }
+    }
+
+    // The last replay kline is not closed yet
+    for kline in replay_klines
+        .iter()
+        .take(replay_klines.len().saturating_sub(1))
+    {
+        signal.update(kline);
+        filters.update(kline);
+        atr.update(kline);
+        write_journal(
+            &mut journal,
+            &JournalEntry::ReplayKline {
+                kline: kline.clone(),
+            },
+        );
+    }
+    let mut minute_timer = Timer::new(FixedUpdate::Minute(1));
+    info!("momentums: {:?}", signal.momentum());
+
+    // Close trades if needed
+    // close_trades(..);
+    let mut metric = BacktestMetric::new(&backtest_config);
+    #[cfg(feature = "exchange-orders")]
+    let mut stop_orders = StopOrders::default();
+    let retry_times = 5;
+    let retry_secs = 5; // secs
+
+    // ===== Live =====
+    let output_trade_log_name = if paper {
+        "paper_trade_output"
+    } else {
+        "live_trade_output"
+    };
+    let output = ExitOutput {
+        output_trade_log: true,
+        output_trade_log_name,
+        api_client_opt: order_client,
+    };
+    loop {
+        if minute_timer.update() {
+            let mut recent_klines_res =
+                task::block_on(api_client.get_klines(&symbol, "1d", None, None, Some("2")));
+            for _ in 0..retry_times {
+                if recent_klines_res.is_err() {
+                    recent_klines_res =
+                        task::block_on(api_client.get_klines(&symbol, "1d", None, None, Some("2")));
+                    info!("Retry get recent klines");
+                } else {
+                    break;
+                }
+                thread::sleep(std::time::Duration::from_secs(retry_secs));
+            }
+            match recent_klines_res {
+                Ok(recent_klines) => {
+                    let curr_kline = recent_klines.last().unwrap();
+                    let usd_balance = if paper {
+                        metric.usd_balance
+                    } else {
+                        match task::block_on(api_client.get_account()) {
+                            Ok(account) => account.get_usd_balance(),
+                            Err(err) => {
+                                // The kline is still crossed on the next minute
+                                warn!("Get account error, {:?}", err);
+                                continue;
+                            }
+                        }
+                    };
+                    // Unrealized losses of the open trades count toward the limits
+                    let equity = usd_balance + unrealized_profit(&trades, curr_kline.close);
+                    if !risk_manager.check(equity) {
+                        write_journal(
+                            &mut journal,
+                            &JournalEntry::Flatten {
+                                kline: curr_kline.clone(),
+                            },
+                        );
+                        close_all_trades(
+                            &mut metric,
+                            &backtest_config,
+                            &mut trades,
+                            curr_kline,
+                            output,
+                        );
+                        #[cfg(feature = "exchange-orders")]
+                        if backtest_config.mirror_stop_orders {
+                            sync_stop_orders(&symbol, order_client, &mut trades, &mut stop_orders);
+                        }
+                        log_trades(&trades, &version);
+                        error!("Trading halted: {:?}", risk_manager.halt_reason());
+                        break;
+                    }
+                    let last_close_timestamp = replay_klines.back().unwrap().close_timestamp;
+
+                    if last_close_timestamp == curr_kline.close_timestamp {
+                        replay_klines.pop_back(); // Update latest kline
+                        replay_klines.push_back(curr_kline.clone());
+
+                        info!("Current usd_balance: {}", usd_balance);
+                        info!("momentums: {:?}", signal.momentum());
+                    } else {
+                        let closed_kline = recent_klines.first().unwrap();
+                        replay_klines.pop_back(); // Update latest kline
+                        replay_klines.push_back(closed_kline.clone());
+                        if let Some((regime_symbol, regime_interval)) = &regime_source {
+                            match task::block_on(api_client.get_klines(
+                                regime_symbol,
+                                regime_interval,
+                                None,
+                                None,
+                                Some("2"),
+                            )) {
+                                Ok(regime_klines) => match regime_klines.first() {
+                                    Some(regime_kline) => {
+                                        filters.update_regime(regime_kline);
+                                        write_journal(
+                                            &mut journal,
+                                            &JournalEntry::RegimeKline {
+                                                kline: regime_kline.clone(),
+                                            },
+                                        );
+                                    }
+                                    None => warn!("Get regime kline error, no kline"),
+                                },
+                                Err(err) => warn!("Get regime kline error, {:?}", err),
+                            }
+                        }
+                        signal.update(closed_kline);
+                        filters.update(closed_kline);
+                        write_journal(
+                            &mut journal,
+                            &JournalEntry::Kline {
+                                kline: closed_kline.clone(),
+                            },
+                        );
+
+                        warn!("kline is crossed: {:?}", replay_klines);
+                        info!("momentums: {:?}", signal.momentum());
+                        let atr_value = atr.update(closed_kline);
+                        sl_tp_exit(
+                            &mut metric,
+                            &backtest_config,
+                            &mut trades,
+                            closed_kline,
+                            atr_value,
+                            output,
+                        );
+                        // Correct the usd_balance after the exits
+                        if !paper {
+                            match task::block_on(api_client.get_account()) {
+                                Ok(account) => metric.usd_balance = account.get_usd_balance(),
+                                Err(err) => {
+                                    warn!(
+                                        "Get account error, keep the booked usd_balance, {:?}",
+                                        err
+                                    )
+                                }
+                            }
+                        }
+                        write_journal(
+                            &mut journal,
+                            &JournalEntry::Account {
+                                usd_balance: metric.usd_balance,
+                            },
+                        );
+                        open_trade(
+                            symbol.clone(),
+                            &mut metric,
+                            &backtest_config,
+                            &mut trades,
+                            signal.momentum(),
+                            &filters,
+                            true,
+                            output_trade_log_name,
+                            closed_kline,
+                            Some(&risk_manager),
+                            order_client,
+                        );
+                        for fill in metric.live_fills.drain(..) {
+                            write_journal(&mut journal, &JournalEntry::Fill { fill });
+                        }
+                        write_journal(
+                            &mut journal,
+                            &JournalEntry::Decision {
+                                close_timestamp: closed_kline.close_timestamp,
+                                trades: trades.clone(),
+                            },
+                        );
+                        #[cfg(feature = "exchange-orders")]
+                        if backtest_config.mirror_stop_orders {
+                            sync_stop_orders(&symbol, order_client, &mut trades, &mut stop_orders);
+                            write_journal(
+                                &mut journal,
+                                &JournalEntry::StopOrders {
+                                    missing: trades
+                                        .iter()
+                                        .filter(|trade| trade.stop_order_missing)
+                                        .map(|trade| trade.entry_ts)
+                                        .collect(),
+                                },
+                            );
+                        }
+                        replay_klines.pop_front();
+                        replay_klines.push_back(curr_kline.clone());
+
+                        log_trades(&trades, &version);
+                    }
+                }
+                Err(err) => {
+                    warn!("Get recent kline error, {:?}", err);
+                }
+            }
+        } else {
+            thread::sleep(std::time::Duration::from_secs(10));
+        }
+    }
+    Ok(())
+}
+
+// A lost entry only breaks the replay of the session, trading goes on
+fn write_journal(journal: &mut Journal, entry: &JournalEntry) {
+    if let Err(err) = journal.write(entry) {
+        error!("Write journal entry error, {}, {:?}", err, entry);
+    }
+}
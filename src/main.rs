@@ -1,49 +1,505 @@
+use std::{fs, path::Path, thread};
+
+use async_std::task;
+use chrono::NaiveDateTime;
 use clap::Parser;
 use momentum::{
-    backtest::Backtest,
-    hypertune::hypertune,
-    types::{BacktestConfig, Cli, SettingConfig},
-    utils::get_klines_from_db,
+    analysis::{analyze, read_hypertune_output},
+    backtest::{self, Backtest, BacktestMetric},
+    config::{ConfigError, ConfigOverrides, InvalidField, Validate, DATETIME_FORMAT},
+    consts::BASKET_COLLECTIONS,
+    divergence::{divergence_report, live_trades, read_fills},
+    hypertune::{basket_hypertune, hypertune, tune_params, SymbolKlines},
+    journal::{read_journal, replay},
+    live::live_trade,
+    monte_carlo::{monte_carlo, MonteCarloConfig},
+    report::{backtest_report, hypertune_report},
+    secrets::{load_credentials, public_api_client},
+    types::{
+        BacktestConfig, BacktestReportArgs, BacktestSettingConfig, Cli, Command, DivergenceArgs,
+        EntryOrder, HypertuneReportArgs, LiveSettingConfig, MonteCarloArgs, OrderSideArg,
+        ReportCommand,
+    },
+    utils::{get_klines_from_db, get_trade_snapshots, sync_klines},
 };
 use serde_json::Value;
-use std::fs::File;
-use trade_utils::types::cli::Mode;
+use trade_utils::{
+    clients::binance::api::SYMBOL_TO_INSTRUMENT_INFO,
+    types::order::{Order, OrderSide},
+};
 
-use log::{info, LevelFilter};
+use log::{error, info, LevelFilter};
 
 use simplelog::{ColorChoice, CombinedLogger, Config, TermLogger, TerminalMode};
 
-fn main() {
-    CombinedLogger::init(vec![TermLogger::new(
-        LevelFilter::Info,
-        Config::default(),
-        TerminalMode::Mixed,
-        ColorChoice::Auto,
-    )])
-    .unwrap();
+fn main() -> Result<(), ConfigError> {
     let args = Cli::parse();
+    match &args.command {
+        // Live sessions log to the files of log4rs.yml
+        Command::Live { .. } | Command::Paper { .. } => {
+            log4rs::init_file("log4rs.yml", Default::default()).unwrap();
+        }
+        _ => {
+            CombinedLogger::init(vec![TermLogger::new(
+                LevelFilter::Info,
+                Config::default(),
+                TerminalMode::Mixed,
+                ColorChoice::Auto,
+            )])
+            .unwrap();
+        }
+    }
     info!("args: {:?}", args);
 
-    let setting_config_file = File::open(&args.setting_config.unwrap()).unwrap();
-    let setting_config: SettingConfig = serde_json::from_reader(setting_config_file).unwrap();
-    let symbol = setting_config.symbol.clone();
-    let collection = setting_config.symbol + &setting_config.collection_postfix;
-    let klines = get_klines_from_db(&setting_config.from, &setting_config.to, &collection);
-    match args.mode {
-        Mode::Backtest => {
-            let backtest_config_file = File::open(&args.backtest_config.unwrap()).unwrap();
-            let backtest_config: BacktestConfig =
-                serde_json::from_reader(backtest_config_file).unwrap();
+    match args.command {
+        Command::Backtest {
+            backtest_config,
+            setting_config,
+            config_args,
+        } => {
+            let overrides = ConfigOverrides::new(config_args.profile, &config_args.set)?;
+            let setting_config: BacktestSettingConfig = overrides.load_setting(&setting_config)?;
+            let backtest_config: BacktestConfig = overrides.load_config(&backtest_config)?;
             info!("backtest_config: {:?}", backtest_config);
-            info!("klines num: {:?}", klines.len());
-            let mut backtest = Backtest::new(&backtest_config, true);
-            backtest.run(&klines, symbol.clone());
+            let (metric, _) = run_backtest(&backtest_config, &setting_config, true);
+            metric.log_sides();
+        }
+        Command::Hypertune {
+            hypertune_config,
+            setting_config,
+            dry_run,
+            cache,
+            no_cache,
+            symbols,
+            basket,
+            basket_objective,
+            config_args,
+        } => {
+            let overrides = ConfigOverrides::new(config_args.profile, &config_args.set)?;
+            let setting_config: BacktestSettingConfig = overrides.load_setting(&setting_config)?;
+            let hypertune_config_value: Value = overrides.config_value(&hypertune_config)?;
+            let invalid = |invalid: InvalidField| ConfigError::Invalid {
+                path: hypertune_config.clone(),
+                field: invalid.field,
+                reason: invalid.reason,
+            };
+            let cache_path = if no_cache {
+                None
+            } else {
+                Some(cache.as_path())
+            };
+            let symbols = if basket {
+                // e.g. BTCUSDT_1d -> BTCUSDT
+                BASKET_COLLECTIONS
+                    .iter()
+                    .map(|collection| collection.split_once('_').unwrap().0.to_owned())
+                    .collect()
+            } else {
+                symbols
+            };
+            if !symbols.is_empty() {
+                basket_hypertune(
+                    &hypertune_config_value,
+                    &basket_klines(&setting_config, &hypertune_config_value, &symbols),
+                    basket_objective,
+                    dry_run,
+                    cache_path,
+                )
+                .map_err(invalid)?;
+                return Ok(());
+            }
+            let klines = get_klines_from_db(
+                &setting_config.from,
+                &setting_config.to,
+                &(setting_config.symbol.clone() + &setting_config.collection_postfix),
+            );
+            let regime_klines = match hypertune_config_value["regime_collection"].as_str() {
+                Some(regime_collection) => {
+                    get_klines_from_db(&setting_config.from, &setting_config.to, regime_collection)
+                }
+                None => Vec::new(),
+            };
+            hypertune(
+                &hypertune_config_value,
+                &klines,
+                &regime_klines,
+                setting_config.symbol.clone(),
+                dry_run,
+                cache_path,
+            )
+            .map_err(invalid)?;
+        }
+        Command::MonteCarlo(monte_carlo_args) => run_monte_carlo(monte_carlo_args)?,
+        Command::Live {
+            backtest_config,
+            setting_config,
+            config_args,
+        } => {
+            let overrides = ConfigOverrides::new(config_args.profile, &config_args.set)?;
+            let setting_config: LiveSettingConfig = overrides.load_setting(&setting_config)?;
+            let backtest_config_path = backtest_config;
+            let backtest_config: BacktestConfig = overrides.load_config(&backtest_config_path)?;
+            check_exchange_orders(&backtest_config_path, &backtest_config)?;
+            live_trade(setting_config, backtest_config, false)?;
+        }
+        Command::Paper {
+            backtest_config,
+            setting_config,
+            config_args,
+        } => {
+            let overrides = ConfigOverrides::new(config_args.profile, &config_args.set)?;
+            let setting_config: LiveSettingConfig = overrides.load_setting(&setting_config)?;
+            let backtest_config: BacktestConfig = overrides.load_config(&backtest_config)?;
+            live_trade(setting_config, backtest_config, true)?;
+        }
+        Command::Sync {
+            setting_config,
+            config_args,
+        } => {
+            let overrides = ConfigOverrides::new(config_args.profile, &config_args.set)?;
+            let setting_config: BacktestSettingConfig = overrides.load_setting(&setting_config)?;
+            // e.g. _1d -> 1d
+            let interval = setting_config.collection_postfix.trim_start_matches('_');
+            let synced = sync_klines(
+                &public_api_client(),
+                &setting_config.symbol,
+                interval,
+                datetime_ms(&setting_config.from),
+                datetime_ms(&setting_config.to),
+                &(setting_config.symbol.clone() + &setting_config.collection_postfix),
+            );
+            info!("Synced {} klines", synced);
+        }
+        Command::Account {
+            setting_config,
+            watch,
+            config_args,
+        } => {
+            let overrides = ConfigOverrides::new(config_args.profile, &config_args.set)?;
+            let setting_config: LiveSettingConfig = overrides.load_setting(&setting_config)?;
+            let api_client =
+                load_credentials(Path::new(&setting_config.secrets_path))?.api_client();
+            loop {
+                let account = task::block_on(api_client.get_account()).unwrap();
+                info!("usd_balance: {}", account.get_usd_balance());
+                if !watch {
+                    break;
+                }
+                thread::sleep(std::time::Duration::from_secs(10));
+            }
+        }
+        Command::Order {
+            setting_config,
+            symbol,
+            side,
+            quantity,
+            config_args,
+        } => {
+            let overrides = ConfigOverrides::new(config_args.profile, &config_args.set)?;
+            let setting_config: LiveSettingConfig = overrides.load_setting(&setting_config)?;
+            let api_client =
+                load_credentials(Path::new(&setting_config.secrets_path))?.api_client();
+            let symbol = symbol.unwrap_or(setting_config.symbol);
+            let instrument_info = SYMBOL_TO_INSTRUMENT_INFO.get(&symbol).unwrap();
+            info!("instrument_info: {:?}", instrument_info);
+            let order_side = match side {
+                OrderSideArg::Buy => OrderSide::Buy,
+                OrderSideArg::Sell => OrderSide::Sell,
+            };
+            let order = Order::market_order(symbol.clone(), order_side, quantity);
+            let place_order_res =
+                task::block_on(api_client.place_order(order, instrument_info)).unwrap();
+            info!("place_order_res: {:?}", place_order_res);
+        }
+        Command::Replay { journal } => {
+            let entries = read_journal(&journal)?;
+            info!(
+                "Replay {} journal entries from {:?}",
+                entries.len(),
+                journal
+            );
+            let result = replay(&entries);
+            if result.mismatches == 0 {
+                info!("All {} decisions are reproduced", result.decisions);
+            } else {
+                error!(
+                    "{} of {} decisions are not reproduced",
+                    result.mismatches, result.decisions
+                );
+                std::process::exit(1);
+            }
+        }
+        Command::Report { report } => match report {
+            ReportCommand::Backtest(report_args) => report_backtest(report_args)?,
+            ReportCommand::Hypertune(report_args) => report_hypertune(report_args)?,
+            ReportCommand::Divergence(divergence_args) => report_divergence(divergence_args)?,
+        },
+    }
+    Ok(())
+}
+
+/// Live stop and limit orders need a build with the exchange-orders feature.
+fn check_exchange_orders(path: &Path, backtest_config: &BacktestConfig) -> Result<(), ConfigError> {
+    let field = if backtest_config.mirror_stop_orders {
+        "mirror_stop_orders"
+    } else if backtest_config.entry_order != EntryOrder::Market {
+        "entry_order"
+    } else {
+        return Ok(());
+    };
+    if cfg!(feature = "exchange-orders") {
+        return Ok(());
+    }
+    Err(ConfigError::Invalid {
+        path: path.to_owned(),
+        field: field.to_owned(),
+        reason: "needs a build with `--features exchange-orders` for live trade".to_owned(),
+    })
+}
+
+fn datetime_ms(datetime: &str) -> i64 {
+    NaiveDateTime::parse_from_str(datetime, DATETIME_FORMAT)
+        .unwrap()
+        .and_utc()
+        .timestamp_millis()
+}
+
+/// Klines of every symbol over the range of the setting config. The regime_collection of the
+/// hypertune config is loaded once and is the regime of every symbol.
+fn basket_klines(
+    setting_config: &BacktestSettingConfig,
+    hypertune_config_value: &Value,
+    symbols: &[String],
+) -> Vec<SymbolKlines> {
+    let regime_klines = match hypertune_config_value["regime_collection"].as_str() {
+        Some(regime_collection) => {
+            get_klines_from_db(&setting_config.from, &setting_config.to, regime_collection)
         }
-        Mode::Hypertune => {
-            let config_file = File::open(&args.hypertune_config.unwrap()).unwrap();
-            let hypertune_config_value: Value = serde_json::from_reader(config_file).unwrap();
-            hypertune(&hypertune_config_value, &klines, symbol.clone());
+        None => Vec::new(),
+    };
+    symbols
+        .iter()
+        .map(|symbol| {
+            let klines = get_klines_from_db(
+                &setting_config.from,
+                &setting_config.to,
+                &(symbol.clone() + &setting_config.collection_postfix),
+            );
+            info!("{} klines num: {}", symbol, klines.len());
+            SymbolKlines {
+                symbol: symbol.clone(),
+                klines,
+                regime_klines: regime_klines.clone(),
+            }
+        })
+        .collect()
+}
+
+/// Backtests over the klines of the setting config, with the regime klines when configured.
+fn run_backtest(
+    backtest_config: &BacktestConfig,
+    setting_config: &BacktestSettingConfig,
+    output_result: bool,
+) -> (BacktestMetric, i64) {
+    let collection = setting_config.symbol.clone() + &setting_config.collection_postfix;
+    let klines = get_klines_from_db(&setting_config.from, &setting_config.to, &collection);
+    info!("klines num: {:?}", klines.len());
+    let mut backtest = Backtest::new(backtest_config, output_result);
+    if let Some(regime_collection) = &backtest_config.regime_collection {
+        let regime_klines =
+            get_klines_from_db(&setting_config.from, &setting_config.to, regime_collection);
+        backtest.set_regime_klines(regime_klines);
+    }
+    let metric = backtest.run(&klines, setting_config.symbol.clone());
+    let kline_ms = match (klines.first(), klines.get(1)) {
+        (Some(first), Some(second)) => second.close_timestamp - first.close_timestamp,
+        _ => 24 * 60 * 60 * 1000,
+    };
+    (metric, kline_ms)
+}
+
+fn report_backtest(args: BacktestReportArgs) -> Result<(), ConfigError> {
+    let overrides = ConfigOverrides::new(args.config_args.profile, &args.config_args.set)?;
+    let setting_config: BacktestSettingConfig = overrides.load_setting(&args.setting_config)?;
+    let backtest_config: BacktestConfig = overrides.load_config(&args.backtest_config)?;
+
+    let collection = setting_config.symbol.clone() + &setting_config.collection_postfix;
+    let klines = get_klines_from_db(&setting_config.from, &setting_config.to, &collection);
+    let regime_klines = match &backtest_config.regime_collection {
+        Some(regime_collection) => {
+            get_klines_from_db(&setting_config.from, &setting_config.to, regime_collection)
+        }
+        None => Vec::new(),
+    };
+    let result = backtest::run_backtest(
+        &backtest_config,
+        &klines,
+        regime_klines,
+        &setting_config.symbol,
+    );
+    fs::write(&args.output, backtest_report(&result, &klines)).unwrap();
+    info!("Report written to {:?}", args.output);
+    Ok(())
+}
+
+fn run_monte_carlo(args: MonteCarloArgs) -> Result<(), ConfigError> {
+    let config = MonteCarloConfig {
+        resampling: args.resampling,
+        simulations: args.simulations as usize,
+        block_size: args.block_size,
+        confidence: args.confidence,
+        ruin_ratio: args.ruin_ratio,
+        seed: args.seed,
+    };
+    let invalid = |invalid: InvalidField| ConfigError::InvalidArg {
+        field: invalid.field,
+        reason: invalid.reason,
+    };
+    // Before the klines are loaded
+    config.validate().map_err(invalid)?;
+    let overrides = ConfigOverrides::new(args.config_args.profile, &args.config_args.set)?;
+    let setting_config: BacktestSettingConfig = overrides.load_setting(&args.setting_config)?;
+    let backtest_config: BacktestConfig = overrides.load_config(&args.backtest_config)?;
+    let collection = setting_config.symbol.clone() + &setting_config.collection_postfix;
+    let klines = get_klines_from_db(&setting_config.from, &setting_config.to, &collection);
+    let regime_klines = match &backtest_config.regime_collection {
+        Some(regime_collection) => {
+            get_klines_from_db(&setting_config.from, &setting_config.to, regime_collection)
         }
-        _ => {}
+        None => Vec::new(),
+    };
+    let result = backtest::run_backtest(
+        &backtest_config,
+        &klines,
+        regime_klines,
+        &setting_config.symbol,
+    );
+    let summary =
+        monte_carlo(&result.trades, backtest_config.initial_captial, &config).map_err(invalid)?;
+    info!(
+        "backtest final_equity: {:.4}, max_drawdown_ratio: {:.4}",
+        summary.backtest_final_equity, summary.backtest_max_drawdown_ratio
+    );
+    for (name, interval) in [
+        ("final_equity", &summary.final_equity),
+        ("max_drawdown_ratio", &summary.max_drawdown_ratio),
+    ] {
+        info!(
+            "{} {}% interval: [{:.4}, {:.4}], median: {:.4}",
+            name,
+            config.confidence * 100.,
+            interval.lower,
+            interval.upper,
+            interval.median
+        );
     }
+    info!(
+        "risk_of_ruin: {:.4} (equity below {:.4})",
+        summary.risk_of_ruin,
+        backtest_config.initial_captial * (1. - config.ruin_ratio)
+    );
+    fs::write(
+        &args.output,
+        serde_json::to_string_pretty(&summary).unwrap(),
+    )
+    .unwrap();
+    info!("Monte Carlo summary written to {:?}", args.output);
+    Ok(())
+}
+
+fn report_hypertune(args: HypertuneReportArgs) -> Result<(), ConfigError> {
+    let overrides = ConfigOverrides::new(args.config_args.profile, &args.config_args.set)?;
+    let hypertune_config_value: Value = overrides.config_value(&args.hypertune_config)?;
+    let tune_fields: Vec<String> = tune_params(&hypertune_config_value)
+        .map_err(|invalid| ConfigError::Invalid {
+            path: args.hypertune_config.clone(),
+            field: invalid.field,
+            reason: invalid.reason,
+        })?
+        .into_iter()
+        .map(|(field, _)| field)
+        .collect();
+    let analysis = analyze(
+        tune_fields,
+        read_hypertune_output(&args.input)?,
+        args.objective,
+    )
+    .map_err(|invalid| ConfigError::Invalid {
+        path: args.input.clone(),
+        field: invalid.field,
+        reason: invalid.reason,
+    })?;
+    for (rank, run) in analysis.ranked().iter().take(args.top).enumerate() {
+        info!(
+            "#{} objective: {:.4}, robustness: {:.4}, {:?}",
+            rank + 1,
+            run.objective,
+            run.robustness,
+            analysis.describe(run)
+        );
+    }
+    fs::write(&args.output, hypertune_report(&analysis, args.top)).unwrap();
+    info!("Report written to {:?}", args.output);
+
+    match analysis.recommended() {
+        Some(run) => {
+            let config =
+                analysis.run_config(&args.hypertune_config, &hypertune_config_value, run)?;
+            info!(
+                "recommended objective: {:.4}, robustness: {:.4}, {:?}",
+                run.objective,
+                run.robustness,
+                analysis.describe(run)
+            );
+            fs::write(
+                &args.recommended,
+                serde_json::to_string_pretty(&config).unwrap(),
+            )
+            .unwrap();
+            info!("Recommended config written to {:?}", args.recommended);
+        }
+        None => error!("No runs in {:?}", args.input),
+    }
+    Ok(())
+}
+
+fn report_divergence(args: DivergenceArgs) -> Result<(), ConfigError> {
+    let overrides = ConfigOverrides::new(args.config_args.profile, &args.config_args.set)?;
+    let setting_config: BacktestSettingConfig = overrides.load_setting(&args.setting_config)?;
+    let backtest_config: BacktestConfig = overrides.load_config(&args.backtest_config)?;
+
+    // Backtest over the same range and config as the live trade
+    let (metric, kline_ms) = run_backtest(&backtest_config, &setting_config, false);
+    let from_ts = datetime_ms(&setting_config.from);
+    let to_ts = datetime_ms(&setting_config.to);
+    let snapshots = task::block_on(get_trade_snapshots(&args.version));
+    let live_trades: Vec<_> = live_trades(&snapshots)
+        .into_iter()
+        .filter(|live_trade| {
+            live_trade.trade.entry_ts >= from_ts && live_trade.trade.entry_ts <= to_ts
+        })
+        .collect();
+    let fills = match &args.fills {
+        Some(path) => read_fills(path),
+        None => Vec::new(),
+    };
+    info!(
+        "live trades: {}, fills: {}, backtest exits: {}",
+        live_trades.len(),
+        fills.len(),
+        metric.trades.len()
+    );
+
+    // Entries are decided on the kline close, so the same decision can only be a kline apart
+    let report = divergence_report(
+        &live_trades,
+        &fills,
+        &metric.trades,
+        args.fill_window_mins * 60 * 1000,
+        kline_ms,
+    );
+    report.log();
+    report.write_csv(&args.output);
+    info!("Report written to {:?}", args.output);
+    Ok(())
 }
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use log::info;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use trade_utils::types::trade::TradeSide;
+
+use crate::{
+    backtest::TradeRecord,
+    config::{InvalidField, Validate},
+};
+
+/// How the trade returns of a simulated path are drawn from the backtest ledger.
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resampling {
+    Shuffle,   // Every trade once in a random order, only the drawdown varies
+    Bootstrap, // Trades drawn with replacement
+    #[default]
+    BlockBootstrap, // Runs of `block_size` consecutive trades drawn with replacement, keeps streaks
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonteCarloConfig {
+    pub resampling: Resampling,
+    pub simulations: usize,
+    pub block_size: usize,
+    pub confidence: f64, // Two-sided, e.g. 0.95 for the 2.5% and 97.5% percentiles
+    pub ruin_ratio: f64, // A path is ruined once its equity loses this part of initial_captial
+    pub seed: Option<u64>,
+}
+
+/// Percentiles of a simulated value.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interval {
+    pub lower: f64,
+    pub median: f64,
+    pub upper: f64,
+}
+
+/// Intervals of the simulated paths. The returns are approximate for pyramided trades, see
+/// `trade_returns`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonteCarloSummary {
+    pub config: MonteCarloConfig,
+    pub trades: usize,
+    pub initial_captial: f64,
+    pub backtest_final_equity: f64,
+    pub backtest_max_drawdown_ratio: f64, // Of the balance after each trade, like the paths
+    pub final_equity: Interval,
+    pub max_drawdown_ratio: Interval,
+    pub risk_of_ruin: f64, // Share of the paths that were ruined
+}
+
+/// Return of every trade on the balance before it, in exit order. The exits of a trade closed
+/// by the take-profit ladder are one trade, its profit is booked at its last exit. Pyramided
+/// trades are open at the same time, but each return is on the balance realized before its
+/// exit, so the paths only approximate the equity of overlapping trades.
+pub fn trade_returns(trades: &[TradeRecord], initial_captial: f64) -> Vec<f64> {
+    // (exit_ts, profit) of each trade, keyed by its entry
+    let mut closed: Vec<(i64, f64)> = Vec::new();
+    let mut trade_index: HashMap<(i64, u64, bool), usize> = HashMap::new();
+    for trade in trades {
+        let entry = (
+            trade.entry_ts,
+            trade.entry_price.to_bits(),
+            trade.entry_side == TradeSide::Buy,
+        );
+        let index = *trade_index.entry(entry).or_insert_with(|| {
+            closed.push((trade.exit_ts, 0.));
+            closed.len() - 1
+        });
+        closed[index].0 = closed[index].0.max(trade.exit_ts);
+        closed[index].1 += trade.profit;
+    }
+    // Stable, trades closed on the same kline keep their order
+    closed.sort_by_key(|(exit_ts, _)| *exit_ts);
+    let mut balance = initial_captial;
+    let mut returns = Vec::new();
+    for (_, profit) in closed {
+        if balance <= 0. {
+            break;
+        }
+        returns.push(profit / balance);
+        balance += profit;
+    }
+    returns
+}
+
+// Final equity and max drawdown ratio of the returns compounded from initial_captial, and
+// whether the equity fell to the ruin level
+fn simulate_path(returns: &[f64], initial_captial: f64, ruin_level: f64) -> (f64, f64, bool) {
+    let mut equity = initial_captial;
+    let mut peak = initial_captial;
+    let mut max_drawdown_ratio: f64 = 0.;
+    let mut ruined = false;
+    for r in returns {
+        equity = (equity * (1. + r)).max(0.);
+        peak = peak.max(equity);
+        max_drawdown_ratio = max_drawdown_ratio.max((peak - equity) / peak);
+        ruined |= equity <= ruin_level;
+    }
+    (equity, max_drawdown_ratio, ruined)
+}
+
+fn resample(
+    returns: &[f64],
+    resampling: Resampling,
+    block_size: usize,
+    rng: &mut StdRng,
+) -> Vec<f64> {
+    match resampling {
+        Resampling::Shuffle => {
+            let mut path = returns.to_vec();
+            path.shuffle(rng);
+            path
+        }
+        Resampling::Bootstrap => (0..returns.len())
+            .map(|_| returns[rng.gen_range(0..returns.len())])
+            .collect(),
+        Resampling::BlockBootstrap => {
+            // Blocks wrap around the end so the last trades are drawn as often as the others
+            let block_size = block_size.clamp(1, returns.len());
+            let mut path = Vec::with_capacity(returns.len() + block_size);
+            while path.len() < returns.len() {
+                let start = rng.gen_range(0..returns.len());
+                path.extend(
+                    (start..start + block_size).map(|index| returns[index % returns.len()]),
+                );
+            }
+            path.truncate(returns.len());
+            path
+        }
+    }
+}
+
+// Nearest-rank percentile of sorted values, `quantile` in [0, 1]
+fn percentile(sorted: &[f64], quantile: f64) -> f64 {
+    let index = (quantile * (sorted.len() - 1) as f64).round() as usize;
+    sorted[index.min(sorted.len() - 1)]
+}
+
+fn interval(mut values: Vec<f64>, confidence: f64) -> Interval {
+    values.sort_by(f64::total_cmp);
+    let tail = (1. - confidence) / 2.;
+    Interval {
+        lower: percentile(&values, tail),
+        median: percentile(&values, 0.5),
+        upper: percentile(&values, 1. - tail),
+    }
+}
+
+/// Simulates `config.simulations` paths of the trade ledger of a backtest, an error names the
+/// field of the config out of its range.
+pub fn monte_carlo(
+    trades: &[TradeRecord],
+    initial_captial: f64,
+    config: &MonteCarloConfig,
+) -> Result<MonteCarloSummary, InvalidField> {
+    config.validate()?;
+    let returns = trade_returns(trades, initial_captial);
+    let ruin_level = initial_captial * (1. - config.ruin_ratio);
+    let (backtest_final_equity, backtest_max_drawdown_ratio, _) =
+        simulate_path(&returns, initial_captial, ruin_level);
+    let mut rng = match config.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut final_equities = Vec::with_capacity(config.simulations);
+    let mut max_drawdown_ratios = Vec::with_capacity(config.simulations);
+    let mut ruined_paths = 0;
+    for _ in 0..config.simulations {
+        let path = if returns.is_empty() {
+            Vec::new()
+        } else {
+            resample(&returns, config.resampling, config.block_size, &mut rng)
+        };
+        let (final_equity, max_drawdown_ratio, ruined) =
+            simulate_path(&path, initial_captial, ruin_level);
+        final_equities.push(final_equity);
+        max_drawdown_ratios.push(max_drawdown_ratio);
+        ruined_paths += ruined as usize;
+    }
+    info!(
+        "{} {:?} paths of {} trades",
+        config.simulations,
+        config.resampling,
+        returns.len()
+    );
+    Ok(MonteCarloSummary {
+        config: config.clone(),
+        trades: returns.len(),
+        initial_captial,
+        backtest_final_equity,
+        backtest_max_drawdown_ratio,
+        final_equity: interval(final_equities, config.confidence),
+        max_drawdown_ratio: interval(max_drawdown_ratios, config.confidence),
+        risk_of_ruin: ruined_paths as f64 / config.simulations as f64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn returns() -> Vec<f64> {
+        (0..10).map(|index| index as f64).collect()
+    }
+
+    fn path(resampling: Resampling, block_size: usize, seed: u64) -> Vec<f64> {
+        resample(
+            &returns(),
+            resampling,
+            block_size,
+            &mut StdRng::seed_from_u64(seed),
+        )
+    }
+
+    #[test]
+    fn interval_takes_nearest_rank_percentiles() {
+        let mut values: Vec<f64> = (1..=101).map(|value| value as f64).collect();
+        values.reverse();
+        let wide = interval(values, 0.9);
+        assert_eq!((wide.lower, wide.median, wide.upper), (6., 51., 96.));
+        let single = interval(vec![3.], 0.95);
+        assert_eq!((single.lower, single.median, single.upper), (3., 3., 3.));
+    }
+
+    #[test]
+    fn resample_is_repeatable_with_a_seed() {
+        for resampling in [
+            Resampling::Shuffle,
+            Resampling::Bootstrap,
+            Resampling::BlockBootstrap,
+        ] {
+            assert_eq!(path(resampling, 3, 7), path(resampling, 3, 7));
+        }
+    }
+
+    #[test]
+    fn shuffle_draws_every_trade_once() {
+        let mut path = path(Resampling::Shuffle, 3, 7);
+        path.sort_by(f64::total_cmp);
+        assert_eq!(path, returns());
+    }
+
+    #[test]
+    fn block_bootstrap_keeps_consecutive_trades() {
+        let path = path(Resampling::BlockBootstrap, 3, 7);
+        assert_eq!(path.len(), returns().len());
+        // Blocks wrap around the end of the ledger
+        for block in path.chunks(3) {
+            for pair in block.windows(2) {
+                assert_eq!(pair[1], (pair[0] + 1.) % 10.);
+            }
+        }
+    }
+
+    #[test]
+    fn monte_carlo_rejects_an_invalid_config() {
+        let config = MonteCarloConfig {
+            resampling: Resampling::Bootstrap,
+            simulations: 10,
+            block_size: 1,
+            confidence: 1.5,
+            ruin_ratio: 0.5,
+            seed: Some(7),
+        };
+        let invalid = monte_carlo(&[], 1000., &config).unwrap_err();
+        assert_eq!(invalid.field, "confidence");
+    }
+}
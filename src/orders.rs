@@ -0,0 +1,278 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_std::task;
+use log::*;
+use trade_utils::{
+    clients::binance::api::{BinanceFuturesApiClient, SYMBOL_TO_INSTRUMENT_INFO},
+    types::{
+        order::{Order, OrderSide, TimeInForce},
+        trade::{Trade, TradeSide},
+    },
+};
+
+use crate::{
+    execution::LiveFill,
+    types::{BacktestConfig, EntryOrder, ManagedTrade},
+};
+
+// Interval of the status queries of a post-only entry while it waits to fill
+const LIMIT_POLL_SECS: f64 = 1.;
+
+fn order_side(trade: &Trade) -> OrderSide {
+    if trade.entry_side == TradeSide::Buy {
+        OrderSide::Buy
+    } else {
+        OrderSide::Sell
+    }
+}
+
+/// Enters the trade with a limit order at its entry price. A post-only order is polled until it
+/// fills or `limit_timeout_secs` passes, then canceled. Order errors are logged and count as not
+/// filled, so the kline loop keeps running with the position the exchange reports.
+pub fn place_limit_entry(
+    api_client: &BinanceFuturesApiClient,
+    config: &BacktestConfig,
+    trade: &Trade,
+) -> LiveFill {
+    let symbol = trade.symbol.clone();
+    let order_side = order_side(trade);
+    let time_in_force = if config.entry_order == EntryOrder::PostOnly {
+        TimeInForce::Gtx
+    } else {
+        TimeInForce::Ioc
+    };
+    let instrument_info = SYMBOL_TO_INSTRUMENT_INFO.get(&symbol).unwrap();
+    let order = Order::limit_order(
+        symbol.clone(),
+        order_side,
+        trade.position,
+        trade.entry_price,
+        time_in_force,
+    );
+    let (mut position, mut notional) =
+        match task::block_on(api_client.place_order(order, instrument_info)) {
+            Ok(order_res) => {
+                info!("place_limit_order_res: {:?}", order_res);
+                let (position, notional) = (
+                    order_res.executed_qty,
+                    order_res.executed_qty * order_res.avg_price,
+                );
+                if config.entry_order == EntryOrder::PostOnly && position < trade.position {
+                    wait_limit_entry(
+                        api_client,
+                        config,
+                        &symbol,
+                        order_res.order_id,
+                        trade.position,
+                    )
+                    .unwrap_or((position, notional))
+                } else {
+                    (position, notional)
+                }
+            }
+            Err(err) => {
+                warn!("Place limit entry error, {:?}", err);
+                (0., 0.)
+            }
+        };
+    let rest_position = trade.position - position;
+    if rest_position > 0. && config.limit_fallback_market {
+        let order = Order::market_order(symbol.clone(), order_side, rest_position);
+        match task::block_on(api_client.place_order(order, instrument_info)) {
+            Ok(order_res) => {
+                info!("place_fallback_order_res: {:?}", order_res);
+                position += order_res.executed_qty;
+                notional += order_res.executed_qty * order_res.avg_price;
+            }
+            Err(err) => warn!("Place fallback market entry error, {:?}", err),
+        }
+    }
+    if position > 0. {
+        LiveFill {
+            position,
+            entry_price: notional / position,
+        }
+    } else {
+        LiveFill {
+            position: 0.,
+            entry_price: trade.entry_price,
+        }
+    }
+}
+
+/// Polls the post-only order until `position` is filled or `limit_timeout_secs` passes and
+/// cancels the rest. Returns the filled position and notional, None when the order could not
+/// be queried at all.
+fn wait_limit_entry(
+    api_client: &BinanceFuturesApiClient,
+    config: &BacktestConfig,
+    symbol: &str,
+    order_id: i64,
+    position: f64,
+) -> Option<(f64, f64)> {
+    let deadline = Instant::now() + Duration::from_secs_f64(config.limit_timeout_secs);
+    let mut last_res = None;
+    loop {
+        match task::block_on(api_client.get_order(symbol, order_id)) {
+            Ok(order_res) => {
+                let filled = order_res.executed_qty >= position;
+                last_res = Some(order_res);
+                if filled {
+                    break;
+                }
+            }
+            Err(err) => warn!("Get limit entry error, {:?}", err),
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep((deadline - now).min(Duration::from_secs_f64(LIMIT_POLL_SECS)));
+    }
+    let filled = last_res
+        .as_ref()
+        .is_some_and(|order_res| order_res.executed_qty >= position);
+    if !filled {
+        match task::block_on(api_client.cancel_order(symbol, order_id)) {
+            Ok(order_res) => {
+                info!("cancel_limit_order_res: {:?}", order_res);
+                last_res = Some(order_res);
+            }
+            // Filled or expired since the last query, so its status is final
+            Err(err) => {
+                warn!("Cancel limit entry error, {:?}", err);
+                if let Ok(order_res) = task::block_on(api_client.get_order(symbol, order_id)) {
+                    last_res = Some(order_res);
+                }
+            }
+        }
+    }
+    last_res.map(|order_res| {
+        (
+            order_res.executed_qty,
+            order_res.executed_qty * order_res.avg_price,
+        )
+    })
+}
+
+// Exchange stop order of an open trade
+#[derive(Debug, Clone)]
+struct StopOrder {
+    entry_ts: i64,
+    entry_side: TradeSide,
+    order_id: i64,
+    sl_price: f64,
+    position: f64,
+}
+
+/// Stop market orders placed for the open trades when the stops are mirrored.
+#[derive(Debug, Default)]
+pub struct StopOrders {
+    orders: Vec<StopOrder>, // One per open trade
+    stale: Vec<StopOrder>,  // Of closed trades, canceled again when it failed
+    cleared: bool,          // Orders left by a previous session are canceled
+}
+
+/// Keeps one stop market order per open trade on the exchange, used when the stops are mirrored.
+/// Only the stops of new trades or with a changed price or position are replaced, the old stop is
+/// canceled before the new one is placed so two stops of a trade are never live at once. A trade
+/// whose stop is not on the exchange gets `stop_order_missing` and exits at market on the close
+/// like without mirrored stops, the stop is synced again on the next kline.
+pub fn sync_stop_orders(
+    symbol: &str,
+    api_client_opt: Option<&BinanceFuturesApiClient>,
+    trades: &mut [ManagedTrade],
+    stop_orders: &mut StopOrders,
+) {
+    let api_client = match api_client_opt {
+        Some(api_client) => api_client,
+        None => return,
+    };
+    if !stop_orders.cleared {
+        match task::block_on(api_client.cancel_all_orders(symbol)) {
+            Ok(cancel_res) => {
+                info!("cancel_all_orders_res: {:?}", cancel_res);
+                stop_orders.cleared = true;
+            }
+            Err(err) => {
+                warn!("Cancel all orders error, {:?}", err);
+                for trade in trades.iter_mut() {
+                    trade.stop_order_missing = true;
+                }
+                return;
+            }
+        }
+    }
+    let instrument_info = SYMBOL_TO_INSTRUMENT_INFO.get(symbol).unwrap();
+    let mut synced: Vec<StopOrder> = Vec::new();
+    let mut orders = std::mem::take(&mut stop_orders.orders);
+    for trade in trades.iter_mut() {
+        let order_side = match trade.entry_side {
+            TradeSide::Buy => OrderSide::Sell,
+            TradeSide::Sell => OrderSide::Buy,
+            TradeSide::None => continue,
+        };
+        let current = orders
+            .iter()
+            .position(|order| {
+                order.entry_ts == trade.entry_ts && order.entry_side == trade.entry_side
+            })
+            .map(|index| orders.remove(index));
+        if let Some(order) = current {
+            if order.sl_price == trade.sl_price && order.position == trade.position {
+                synced.push(order);
+                trade.stop_order_missing = false;
+                continue;
+            }
+            match task::block_on(api_client.cancel_order(symbol, order.order_id)) {
+                Ok(cancel_res) => info!("cancel_stop_order_res: {:?}", cancel_res),
+                // The old stop may still be live at its price, so no new one is placed
+                Err(err) => {
+                    warn!("Cancel stop order error, {:?}", err);
+                    synced.push(order);
+                    trade.stop_order_missing = true;
+                    continue;
+                }
+            }
+        }
+        let order = Order::stop_market_order(
+            symbol.to_owned(),
+            order_side,
+            trade.position,
+            trade.sl_price,
+        );
+        match task::block_on(api_client.place_order(order, instrument_info)) {
+            Ok(place_order_res) => {
+                info!("place_stop_order_res: {:?}", place_order_res);
+                synced.push(StopOrder {
+                    entry_ts: trade.entry_ts,
+                    entry_side: trade.entry_side.clone(),
+                    order_id: place_order_res.order_id,
+                    sl_price: trade.sl_price,
+                    position: trade.position,
+                });
+                trade.stop_order_missing = false;
+            }
+            Err(err) => {
+                warn!("Place stop order error, {:?}", err);
+                trade.stop_order_missing = true;
+            }
+        }
+    }
+    // Stops of closed trades
+    let mut stale: Vec<StopOrder> = std::mem::take(&mut stop_orders.stale);
+    stale.append(&mut orders);
+    for order in stale {
+        match task::block_on(api_client.cancel_order(symbol, order.order_id)) {
+            Ok(cancel_res) => info!("cancel_stop_order_res: {:?}", cancel_res),
+            Err(err) => {
+                warn!("Cancel stop order error, {:?}", err);
+                stop_orders.stale.push(order);
+            }
+        }
+    }
+    stop_orders.orders = synced;
+}
@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+
+use log::info;
+use trade_utils::types::{kline::Kline, trade::TradeSide};
+
+use crate::{indicators::Ema, types::BacktestConfig};
+
+const DAY_MS: i64 = 86_400_000;
+const WEEK_MS: i64 = 7 * DAY_MS;
+
+// Start of the bars like the exchange: bars of whole weeks start on Mondays, the first
+// is 1970-01-05 00:00 UTC, and the others on the unix epoch
+fn bar_epoch_ms(bar_ms: i64) -> i64 {
+    if bar_ms % WEEK_MS == 0 {
+        4 * DAY_MS
+    } else {
+        0
+    }
+}
+
+/// Higher-timeframe trend from the EMA slope of klines aggregated `regime_bars` at a time,
+/// e.g. weekly bars out of daily klines. Buy only in an up regime and sell only in a down one.
+/// Bars are cut by the kline timestamps, so the backtest and live trade see the same bars
+/// wherever they start feeding.
+pub struct RegimeFilter {
+    bars: usize,
+    slope_bars: usize,
+    last_close_timestamp: i64,
+    bar_index: Option<i64>, // Of the current higher-timeframe bar
+    bar_closed: bool,
+    last_close: f64,
+    ema: Ema,
+    emas: VecDeque<f64>,
+}
+
+impl RegimeFilter {
+    /// None when `regime_ema_period` is 0.
+    pub fn new(config: &BacktestConfig) -> Option<RegimeFilter> {
+        if config.regime_ema_period == 0 {
+            return None;
+        }
+        Some(RegimeFilter {
+            bars: config.regime_bars.max(1),
+            slope_bars: config.regime_slope_bars.max(1),
+            last_close_timestamp: i64::MIN,
+            bar_index: None,
+            bar_closed: false,
+            last_close: 0.,
+            ema: Ema::new(config.regime_ema_period),
+            emas: VecDeque::new(),
+        })
+    }
+
+    /// Base klines needed before the regime is known.
+    pub fn warmup_bars(&self) -> usize {
+        self.bars * (self.ema.period() + self.slope_bars)
+    }
+
+    /// Klines already seen are ignored, so the caller can feed overlapping batches.
+    pub fn update(&mut self, kline: &Kline) {
+        if kline.close_timestamp <= self.last_close_timestamp {
+            return;
+        }
+        self.last_close_timestamp = kline.close_timestamp;
+        let kline_ms = kline.close_timestamp - kline.open_timestamp + 1;
+        let bar_ms = kline_ms * self.bars as i64;
+        let epoch_ms = bar_epoch_ms(bar_ms);
+        let bar_index = (kline.open_timestamp - epoch_ms).div_euclid(bar_ms);
+        if self.bar_index != Some(bar_index) {
+            // A bar whose last klines are missing closes at its last kline
+            if self.bar_index.is_some() && !self.bar_closed {
+                self.close_bar(self.last_close);
+            }
+            self.bar_index = Some(bar_index);
+            self.bar_closed = false;
+        }
+        self.last_close = kline.close;
+        // The close of the last base kline is the close of the higher-timeframe bar
+        if (kline.close_timestamp + 1 - epoch_ms).rem_euclid(bar_ms) == 0 {
+            self.close_bar(kline.close);
+            self.bar_closed = true;
+        }
+    }
+
+    fn close_bar(&mut self, close: f64) {
+        if let Some(ema) = self.ema.update_value(close) {
+            self.emas.push_back(ema);
+            if self.emas.len() > self.slope_bars + 1 {
+                self.emas.pop_front();
+            }
+        }
+    }
+
+    pub fn slope(&self) -> Option<f64> {
+        if self.emas.len() > self.slope_bars {
+            Some(self.emas[self.emas.len() - 1] - self.emas[0])
+        } else {
+            None
+        }
+    }
+
+    pub fn allows(&self, entry_side: &TradeSide) -> bool {
+        let allowed = match (self.slope(), entry_side) {
+            (Some(slope), TradeSide::Buy) => slope > 0.,
+            (Some(slope), TradeSide::Sell) => slope < 0.,
+            _ => false,
+        };
+        if !allowed {
+            info!(
+                "Entry {:?} is filtered by regime slope {:?}",
+                entry_side,
+                self.slope()
+            );
+        }
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn filter(regime_bars: usize) -> RegimeFilter {
+        let config: BacktestConfig = serde_json::from_value(json!({
+            "initial_captial": 1000.0,
+            "fee_rate": 0.0004,
+            "entry_portion": 0.5,
+            "look_back_count": 2,
+            "risk_portion": 0.05,
+            "tp_ratio": 2.0,
+            "candle_filter": false,
+            "regime_ema_period": 2,
+            "regime_bars": regime_bars
+        }))
+        .unwrap();
+        RegimeFilter::new(&config).unwrap()
+    }
+
+    // Open timestamps of the klines that close a higher-timeframe bar
+    fn bar_closes(regime_bars: usize, kline_ms: i64, first_ms: i64, count: i64) -> Vec<i64> {
+        let mut filter = filter(regime_bars);
+        let mut closes = Vec::new();
+        for index in 0..count {
+            let open_timestamp = first_ms + index * kline_ms;
+            filter.update(&Kline {
+                open_timestamp,
+                close_timestamp: open_timestamp + kline_ms - 1,
+                open: 100.,
+                high: 100.,
+                low: 100.,
+                close: 100.,
+                volume: 1.,
+            });
+            if filter.bar_closed {
+                closes.push(open_timestamp);
+                filter.bar_closed = false;
+            }
+        }
+        closes
+    }
+
+    // 2024-01-01 00:00 UTC, a Monday
+    const MONDAY_MS: i64 = 1_704_067_200_000;
+
+    #[test]
+    fn weekly_bars_close_on_sundays() {
+        let closes = bar_closes(7, DAY_MS, MONDAY_MS + 2 * DAY_MS, 16);
+        assert_eq!(
+            closes,
+            vec![MONDAY_MS + 6 * DAY_MS, MONDAY_MS + 13 * DAY_MS]
+        );
+    }
+
+    #[test]
+    fn other_bars_are_aligned_to_the_unix_epoch() {
+        // The exchange starts 3 day bars on multiples of 3 days from the epoch, the Monday is
+        // one day past one
+        let closes = bar_closes(3, DAY_MS, MONDAY_MS, 7);
+        assert_eq!(closes, vec![MONDAY_MS + DAY_MS, MONDAY_MS + 4 * DAY_MS]);
+        let hour_ms = DAY_MS / 24;
+        let closes = bar_closes(4, hour_ms, MONDAY_MS + hour_ms, 9);
+        assert_eq!(
+            closes,
+            vec![MONDAY_MS + 3 * hour_ms, MONDAY_MS + 7 * hour_ms]
+        );
+    }
+}
@@ -0,0 +1,608 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use chrono::{DateTime, Datelike};
+use serde_json::Value;
+use trade_utils::types::kline::Kline;
+use trade_utils::types::trade::TradeSide;
+
+use crate::{
+    analysis::{Heatmap, HypertuneAnalysis, HypertuneRun},
+    backtest::BacktestResult,
+};
+
+const CHART_WIDTH: f64 = 960.;
+const CHART_HEIGHT: f64 = 280.;
+const PADDING: f64 = 56.;
+const HISTOGRAM_BINS: usize = 20;
+const RUN_COLUMNS: [&str; 5] = [
+    "usd_balance",
+    "total_profit",
+    "win_rate",
+    "max_drawdown",
+    "max_drawdown_ratio",
+];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const STYLE: &str = "body { font-family: sans-serif; margin: 24px; color: #222; }
+h1 { font-size: 22px; } h2 { font-size: 17px; margin-top: 32px; }
+table { border-collapse: collapse; font-size: 13px; }
+td, th { border: 1px solid #ddd; padding: 4px 10px; text-align: right; }
+th { background: #f4f4f4; }
+svg { background: #fff; border: 1px solid #ddd; }
+svg text { font-size: 11px; fill: #555; }
+.legend span { display: inline-block; margin-right: 16px; font-size: 13px; }";
+
+/// Maps timestamps or values into the plot area of a chart.
+struct Scale {
+    x_min: f64,
+    x_max: f64,
+    y_min: f64,
+    y_max: f64,
+}
+
+impl Scale {
+    fn new(x_min: f64, x_max: f64, y_min: f64, y_max: f64) -> Scale {
+        // A flat series still needs a range to draw in
+        let (x_min, x_max) = if x_max > x_min {
+            (x_min, x_max)
+        } else {
+            (x_min - 1., x_min + 1.)
+        };
+        let (y_min, y_max) = if y_max > y_min {
+            (y_min, y_max)
+        } else {
+            (y_min - 1., y_min + 1.)
+        };
+        Scale {
+            x_min,
+            x_max,
+            y_min,
+            y_max,
+        }
+    }
+
+    fn x(&self, x: f64) -> f64 {
+        PADDING + (x - self.x_min) / (self.x_max - self.x_min) * (CHART_WIDTH - 2. * PADDING)
+    }
+
+    fn y(&self, y: f64) -> f64 {
+        CHART_HEIGHT
+            - PADDING
+            - (y - self.y_min) / (self.y_max - self.y_min) * (CHART_HEIGHT - 2. * PADDING)
+    }
+}
+
+fn min_max(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values.fold((f64::MAX, f64::MIN), |(min, max), value| {
+        (min.min(value), max.max(value))
+    })
+}
+
+fn date(ts: i64) -> String {
+    // Out of range for the axes of a chart without points
+    DateTime::from_timestamp_millis(ts)
+        .map(|datetime| datetime.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Svg with the y axis labels at min, middle and max and the dates of the x range.
+fn svg_open(scale: &Scale, y_label: fn(f64) -> String, date_axis: bool) -> String {
+    let mut svg = format!(
+        "<svg viewBox=\"0 0 {w} {h}\" width=\"{w}\" height=\"{h}\" xmlns=\"http://www.w3.org/2000/svg\">",
+        w = CHART_WIDTH,
+        h = CHART_HEIGHT
+    );
+    for y in [scale.y_min, (scale.y_min + scale.y_max) / 2., scale.y_max] {
+        write!(
+            svg,
+            "<line x1=\"{x1}\" x2=\"{x2}\" y1=\"{y:.1}\" y2=\"{y:.1}\" stroke=\"#eee\"/>\
+             <text x=\"{tx}\" y=\"{ty:.1}\" text-anchor=\"end\">{label}</text>",
+            x1 = PADDING,
+            x2 = CHART_WIDTH - PADDING,
+            y = scale.y(y),
+            tx = PADDING - 6.,
+            ty = scale.y(y) + 4.,
+            label = y_label(y)
+        )
+        .unwrap();
+    }
+    let (start, end) = if date_axis {
+        (date(scale.x_min as i64), date(scale.x_max as i64))
+    } else {
+        (y_label(scale.x_min), y_label(scale.x_max))
+    };
+    write!(
+        svg,
+        "<text x=\"{x1}\" y=\"{y}\">{start}</text><text x=\"{x2}\" y=\"{y}\" text-anchor=\"end\">{end}</text>",
+        x1 = PADDING,
+        x2 = CHART_WIDTH - PADDING,
+        y = CHART_HEIGHT - PADDING + 18.,
+        start = start,
+        end = end
+    )
+    .unwrap();
+    svg
+}
+
+fn polyline(scale: &Scale, points: impl Iterator<Item = (f64, f64)>, color: &str) -> String {
+    let points: Vec<String> = points
+        .map(|(x, y)| format!("{:.1},{:.1}", scale.x(x), scale.y(y)))
+        .collect();
+    format!(
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"1.5\"/>",
+        points.join(" "),
+        color
+    )
+}
+
+fn usd(value: f64) -> String {
+    format!("{:.2}", value)
+}
+
+fn percent(value: f64) -> String {
+    format!("{:.1}%", value)
+}
+
+fn legend(items: &[(&str, &str)]) -> String {
+    let items: Vec<String> = items
+        .iter()
+        .map(|(color, name)| format!("<span style=\"color: {}\">&#9632; {}</span>", color, name))
+        .collect();
+    format!("<div class=\"legend\">{}</div>", items.join(""))
+}
+
+fn equity_chart(result: &BacktestResult) -> String {
+    let curve = &result.equity_curve;
+    let (x_min, x_max) = min_max(curve.iter().map(|point| point.timestamp as f64));
+    let (y_min, y_max) = min_max(
+        curve
+            .iter()
+            .flat_map(|point| [point.equity, point.usd_balance]),
+    );
+    let scale = Scale::new(x_min, x_max, y_min, y_max);
+    let mut svg = svg_open(&scale, usd, true);
+    svg += &polyline(
+        &scale,
+        curve
+            .iter()
+            .map(|point| (point.timestamp as f64, point.usd_balance)),
+        "#999",
+    );
+    svg += &polyline(
+        &scale,
+        curve
+            .iter()
+            .map(|point| (point.timestamp as f64, point.equity)),
+        "#1f77b4",
+    );
+    svg += "</svg>";
+    legend(&[("#1f77b4", "equity"), ("#999", "usd_balance")]) + &svg
+}
+
+fn drawdown_chart(result: &BacktestResult) -> String {
+    let mut peak = result.metrics.initial_captial;
+    let drawdowns: Vec<(f64, f64)> = result
+        .equity_curve
+        .iter()
+        .map(|point| {
+            peak = peak.max(point.equity);
+            let drawdown = if peak > 0. {
+                (point.equity - peak) / peak * 100.
+            } else {
+                0.
+            };
+            (point.timestamp as f64, drawdown)
+        })
+        .collect();
+    let (x_min, x_max) = min_max(drawdowns.iter().map(|(ts, _)| *ts));
+    let (y_min, _) = min_max(drawdowns.iter().map(|(_, drawdown)| *drawdown));
+    let scale = Scale::new(x_min, x_max, y_min.min(0.), 0.);
+    let mut svg = svg_open(&scale, percent, true);
+    if let (Some(first), Some(last)) = (drawdowns.first(), drawdowns.last()) {
+        let mut points = format!("{:.1},{:.1}", scale.x(first.0), scale.y(0.));
+        for (ts, drawdown) in drawdowns.iter() {
+            write!(points, " {:.1},{:.1}", scale.x(*ts), scale.y(*drawdown)).unwrap();
+        }
+        write!(points, " {:.1},{:.1}", scale.x(last.0), scale.y(0.)).unwrap();
+        write!(
+            svg,
+            "<polygon points=\"{}\" fill=\"#d62728\" fill-opacity=\"0.35\" stroke=\"#d62728\"/>",
+            points
+        )
+        .unwrap();
+    }
+    svg += "</svg>";
+    svg
+}
+
+/// Return of every month from the equity at the end of the previous month.
+fn monthly_returns(result: &BacktestResult) -> String {
+    let mut month_ends: BTreeMap<(i32, u32), f64> = BTreeMap::new();
+    for point in result.equity_curve.iter() {
+        let datetime = DateTime::from_timestamp_millis(point.timestamp)
+            .unwrap()
+            .naive_utc();
+        month_ends.insert((datetime.year(), datetime.month()), point.equity);
+    }
+    let mut returns: BTreeMap<(i32, u32), f64> = BTreeMap::new();
+    let mut previous = result.metrics.initial_captial;
+    for (month, equity) in month_ends.iter() {
+        returns.insert(*month, (equity / previous - 1.) * 100.);
+        previous = *equity;
+    }
+    let (min_return, max_return) = min_max(returns.values().cloned());
+    let max_abs = min_return.abs().max(max_return.abs()).max(f64::EPSILON);
+
+    let mut html = "<table><tr><th>year</th>".to_string();
+    for month in MONTHS {
+        write!(html, "<th>{}</th>", month).unwrap();
+    }
+    html += "<th>year</th></tr>";
+    let mut years: Vec<i32> = returns.keys().map(|(year, _)| *year).collect();
+    years.dedup();
+    for year in years {
+        write!(html, "<tr><th>{}</th>", year).unwrap();
+        let mut year_growth = 1.;
+        for month in 1..=12 {
+            match returns.get(&(year, month)) {
+                Some(month_return) => {
+                    year_growth *= 1. + month_return / 100.;
+                    // Green for gains and red for losses, stronger for the larger returns
+                    let alpha = 0.1 + 0.7 * month_return.abs() / max_abs;
+                    let color = if *month_return >= 0. {
+                        format!("rgba(44, 160, 44, {:.2})", alpha)
+                    } else {
+                        format!("rgba(214, 39, 40, {:.2})", alpha)
+                    };
+                    write!(
+                        html,
+                        "<td style=\"background: {}\">{}</td>",
+                        color,
+                        percent(*month_return)
+                    )
+                    .unwrap();
+                }
+                None => html += "<td></td>",
+            }
+        }
+        write!(html, "<td>{}</td></tr>", percent((year_growth - 1.) * 100.)).unwrap();
+    }
+    html += "</table>";
+    html
+}
+
+/// Histogram of the profit of the whole trades, the exits of a trade are summed.
+fn trade_distribution(result: &BacktestResult) -> String {
+    let mut trade_profits: BTreeMap<(i64, bool), f64> = BTreeMap::new();
+    for record in result.trades.iter() {
+        *trade_profits
+            .entry((record.entry_ts, record.entry_side == TradeSide::Buy))
+            .or_default() += record.profit;
+    }
+    if trade_profits.is_empty() {
+        return "<p>No trades</p>".to_string();
+    }
+    let (min_profit, max_profit) = min_max(trade_profits.values().cloned());
+    let bin_width = ((max_profit - min_profit) / HISTOGRAM_BINS as f64).max(f64::EPSILON);
+    let mut bins = [0usize; HISTOGRAM_BINS];
+    for profit in trade_profits.values() {
+        let bin = ((profit - min_profit) / bin_width) as usize;
+        bins[bin.min(HISTOGRAM_BINS - 1)] += 1;
+    }
+    let max_count = *bins.iter().max().unwrap() as f64;
+    let scale = Scale::new(
+        min_profit,
+        min_profit + bin_width * HISTOGRAM_BINS as f64,
+        0.,
+        max_count,
+    );
+    let mut svg = svg_open(&scale, usd, false);
+    for (index, count) in bins.iter().enumerate() {
+        let left = min_profit + bin_width * index as f64;
+        let color = if left + bin_width / 2. >= 0. {
+            "#2ca02c"
+        } else {
+            "#d62728"
+        };
+        write!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"><title>{} trades</title></rect>",
+            scale.x(left) + 1.,
+            scale.y(*count as f64),
+            (scale.x(left + bin_width) - scale.x(left) - 2.).max(1.),
+            scale.y(0.) - scale.y(*count as f64),
+            color,
+            count
+        )
+        .unwrap();
+    }
+    svg += "</svg>";
+    let wins = trade_profits
+        .values()
+        .filter(|profit| **profit > 0.)
+        .count();
+    format!(
+        "<p>{} trades, {} wins, profit from {} to {}</p>{}",
+        trade_profits.len(),
+        wins,
+        usd(min_profit),
+        usd(max_profit),
+        svg
+    )
+}
+
+/// Close prices with the entries as triangles and the exits as circles.
+fn price_chart(result: &BacktestResult, klines: &[Kline]) -> String {
+    let (x_min, x_max) = min_max(klines.iter().map(|kline| kline.close_timestamp as f64));
+    let (y_min, y_max) = min_max(klines.iter().flat_map(|kline| [kline.low, kline.high]));
+    let scale = Scale::new(x_min, x_max, y_min, y_max);
+    let mut svg = svg_open(&scale, usd, true);
+    svg += &polyline(
+        &scale,
+        klines
+            .iter()
+            .map(|kline| (kline.close_timestamp as f64, kline.close)),
+        "#555",
+    );
+    let mut last_entry = None;
+    for record in result.trades.iter() {
+        let is_buy = record.entry_side == TradeSide::Buy;
+        let color = if is_buy { "#2ca02c" } else { "#d62728" };
+        // The exits of the take-profit ladder share the entry
+        if last_entry != Some(record.entry_ts) {
+            last_entry = Some(record.entry_ts);
+            let (x, y) = (scale.x(record.entry_ts as f64), scale.y(record.entry_price));
+            let tip = if is_buy { y - 6. } else { y + 6. };
+            write!(
+                svg,
+                "<polygon points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"{}\"><title>{} {:?} {:.4}</title></polygon>",
+                x,
+                tip,
+                x - 5.,
+                y,
+                x + 5.,
+                y,
+                color,
+                date(record.entry_ts),
+                record.entry_side,
+                record.entry_price
+            )
+            .unwrap();
+        }
+        write!(
+            svg,
+            "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"3.5\" fill=\"none\" stroke=\"{}\"><title>{} exit {:.4}, profit {:.4}</title></circle>",
+            scale.x(record.exit_ts as f64),
+            scale.y(record.exit_price),
+            color,
+            date(record.exit_ts),
+            record.exit_price,
+            record.profit
+        )
+        .unwrap();
+    }
+    svg += "</svg>";
+    legend(&[
+        ("#555", "close"),
+        ("#2ca02c", "long entry &#9650; / exit &#9675;"),
+        ("#d62728", "short entry &#9660; / exit &#9675;"),
+    ]) + &svg
+}
+
+fn metrics_table(result: &BacktestResult) -> String {
+    let metrics = &result.metrics;
+    let rows = [
+        ("initial_captial", usd(metrics.initial_captial)),
+        ("usd_balance", usd(metrics.usd_balance)),
+        (
+            "return",
+            percent((metrics.usd_balance / metrics.initial_captial - 1.) * 100.),
+        ),
+        ("max_usd", usd(metrics.max_usd)),
+        ("min_usd", usd(metrics.min_usd)),
+        ("win", metrics.win.to_string()),
+        ("lose", metrics.lose.to_string()),
+        ("win_rate", format!("{:.4}", metrics.win_rate)),
+        ("partial_exits", metrics.partial_exits.to_string()),
+        ("total_profit", usd(metrics.total_profit)),
+        ("total_fee", usd(metrics.total_fee)),
+        ("max_drawdown", usd(metrics.max_drawdown)),
+        (
+            "max_drawdown_ratio",
+            percent(metrics.max_drawdown_ratio * 100.),
+        ),
+        ("sharpe_ratio", format!("{:.4}", metrics.sharpe_ratio)),
+    ];
+    let mut html = "<table>".to_string();
+    for (name, value) in rows {
+        write!(html, "<tr><th>{}</th><td>{}</td></tr>", name, value).unwrap();
+    }
+    html += "</table><h2>Sides</h2><table><tr><th></th><th>win</th><th>lose</th><th>win_rate</th><th>total_profit</th><th>total_fee</th><th>max_drawdown</th></tr>";
+    for (name, side) in [("long", &metrics.long), ("short", &metrics.short)] {
+        write!(
+            html,
+            "<tr><th>{}</th><td>{}</td><td>{}</td><td>{:.4}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            name,
+            side.win,
+            side.lose,
+            side.win_rate(),
+            usd(side.total_profit),
+            usd(side.total_fee),
+            usd(side.max_drawdown)
+        )
+        .unwrap();
+    }
+    html += "</table>";
+    html
+}
+
+/// Html report of the backtest with inline svg charts, it needs nothing but a browser.
+pub fn backtest_report(result: &BacktestResult, klines: &[Kline]) -> String {
+    let title = format!("{} backtest", escape(&result.symbol));
+    let period = match (result.equity_curve.first(), result.equity_curve.last()) {
+        (Some(first), Some(last)) => {
+            format!("{} to {}", date(first.timestamp), date(last.timestamp))
+        }
+        _ => "no klines".to_string(),
+    };
+    let sections = [
+        ("Metrics", metrics_table(result)),
+        ("Equity", equity_chart(result)),
+        ("Drawdown", drawdown_chart(result)),
+        ("Monthly returns", monthly_returns(result)),
+        ("Trade distribution", trade_distribution(result)),
+        ("Trades", price_chart(result, klines)),
+    ];
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title><style>{style}</style></head><body><h1>{title}</h1><p>{period}</p>",
+        title = title,
+        style = STYLE,
+        period = period
+    );
+    for (name, section) in sections {
+        write!(html, "<h2>{}</h2>{}", name, section).unwrap();
+    }
+    html += "</body></html>\n";
+    html
+}
+
+fn runs_table(analysis: &HypertuneAnalysis, runs: &[&HypertuneRun]) -> String {
+    let mut html = "<table><tr><th>#</th>".to_string();
+    for field in analysis.tune_fields.iter() {
+        write!(html, "<th>{}</th>", escape(field)).unwrap();
+    }
+    html += "<th>objective</th><th>robustness</th>";
+    for column in RUN_COLUMNS {
+        write!(html, "<th>{}</th>", column).unwrap();
+    }
+    html += "</tr>";
+    for (rank, run) in runs.iter().enumerate() {
+        write!(html, "<tr><td>{}</td>", rank + 1).unwrap();
+        for value in run.params.iter() {
+            write!(html, "<td>{}</td>", escape(&value.to_string())).unwrap();
+        }
+        write!(
+            html,
+            "<td>{:.4}</td><td>{:.4}</td>",
+            run.objective, run.robustness
+        )
+        .unwrap();
+        for column in RUN_COLUMNS {
+            let cell = run.columns.get(column).map(String::as_str).unwrap_or("");
+            write!(html, "<td>{}</td>", escape(cell)).unwrap();
+        }
+        html += "</tr>";
+    }
+    html += "</table>";
+    html
+}
+
+/// Heatmap table, the cell of the recommended run is outlined.
+fn heatmap_table(heatmap: &Heatmap, recommended: Option<(&Value, &Value)>) -> String {
+    let (min_value, max_value) = min_max(heatmap.cells.iter().flatten().flatten().cloned());
+    let range = (max_value - min_value).max(f64::EPSILON);
+    let mut html = format!(
+        "<table><tr><th>{} \\ {}</th>",
+        escape(&heatmap.y_field),
+        escape(&heatmap.x_field)
+    );
+    for x_value in heatmap.x_values.iter() {
+        write!(html, "<th>{}</th>", escape(&x_value.to_string())).unwrap();
+    }
+    html += "</tr>";
+    for (y_value, row) in heatmap.y_values.iter().zip(heatmap.cells.iter()) {
+        write!(html, "<tr><th>{}</th>", escape(&y_value.to_string())).unwrap();
+        for (x_value, cell) in heatmap.x_values.iter().zip(row.iter()) {
+            let border = if recommended == Some((x_value, y_value)) {
+                "; outline: 2px solid #000"
+            } else {
+                ""
+            };
+            match cell {
+                Some(value) => {
+                    // Red at the worst cell to green at the best
+                    let ratio = (value - min_value) / range;
+                    write!(
+                        html,
+                        "<td style=\"background: rgb({}, {}, 80){}\">{:.2}</td>",
+                        (230. - 150. * ratio) as u8,
+                        (90. + 120. * ratio) as u8,
+                        border,
+                        value
+                    )
+                    .unwrap();
+                }
+                None => html += "<td></td>",
+            }
+        }
+        html += "</tr>";
+    }
+    html += "</table>";
+    html
+}
+
+/// Html report of the hypertune runs, the rankings and the heatmaps of the tuned fields.
+pub fn hypertune_report(analysis: &HypertuneAnalysis, top: usize) -> String {
+    let title = format!("Hypertune of {} configs", analysis.runs.len());
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title><style>{style}</style></head><body><h1>{title}</h1><p>objective: {objective:?}, robustness is the mean objective of the config and its grid neighbours less their standard deviation</p>",
+        title = title,
+        style = STYLE,
+        objective = analysis.objective
+    );
+    let recommended = analysis.recommended();
+    if let Some(run) = recommended {
+        write!(html, "<h2>Recommended</h2>{}", runs_table(analysis, &[run])).unwrap();
+    }
+    let ranked: Vec<&HypertuneRun> = analysis.ranked().into_iter().take(top).collect();
+    write!(
+        html,
+        "<h2>Top {} by objective</h2>{}",
+        top,
+        runs_table(analysis, &ranked)
+    )
+    .unwrap();
+    let robust_ranked: Vec<&HypertuneRun> =
+        analysis.robust_ranked().into_iter().take(top).collect();
+    write!(
+        html,
+        "<h2>Top {} by robustness</h2>{}",
+        top,
+        runs_table(analysis, &robust_ranked)
+    )
+    .unwrap();
+    for heatmap in analysis.heatmaps() {
+        let recommended_cell = recommended.map(|run| {
+            let x_index = analysis
+                .tune_fields
+                .iter()
+                .position(|field| *field == heatmap.x_field)
+                .unwrap();
+            let y_index = analysis
+                .tune_fields
+                .iter()
+                .position(|field| *field == heatmap.y_field)
+                .unwrap();
+            (&run.params[x_index], &run.params[y_index])
+        });
+        write!(
+            html,
+            "<h2>{} vs {}</h2><p>best objective over the other tuned fields</p>{}",
+            escape(&heatmap.y_field),
+            escape(&heatmap.x_field),
+            heatmap_table(&heatmap, recommended_cell)
+        )
+        .unwrap();
+    }
+    html += "</body></html>\n";
+    html
+}
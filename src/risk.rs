@@ -0,0 +1,310 @@
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+
+use chrono::Utc;
+use log::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ConfigError, types::ManagedTrade};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RiskConfig {
+    pub max_open_trades: Option<usize>,
+    pub max_daily_loss: Option<f64>, // Portion of the equity at the start of the UTC day
+    pub max_drawdown: Option<f64>,   // Portion of the peak equity
+    pub max_position_notional: Option<f64>, // Total usd notional of the open trades
+    #[serde(default = "default_state_path")]
+    pub state_path: String,
+}
+
+fn default_state_path() -> String {
+    "./risk_state.json".to_owned()
+}
+
+impl Default for RiskConfig {
+    fn default() -> Self {
+        RiskConfig {
+            max_open_trades: None,
+            max_daily_loss: None,
+            max_drawdown: None,
+            max_position_notional: None,
+            state_path: default_state_path(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RiskState {
+    pub halted: bool,
+    pub halt_reason: Option<String>,
+    pub peak_usd: f64,
+    pub day: String,
+    pub day_start_usd: f64,
+}
+
+pub struct RiskManager {
+    config: RiskConfig,
+    state: RiskState,
+}
+
+impl RiskManager {
+    /// Restores the state from `state_path` so a halted session stays halted after a restart. An
+    /// unreadable or corrupt state file is an error, trading should not start without it.
+    pub fn new(config: &RiskConfig) -> Result<RiskManager, ConfigError> {
+        let path = PathBuf::from(&config.state_path);
+        let state = if path.exists() {
+            let file = File::open(&path).map_err(|source| ConfigError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            serde_json::from_reader(file).map_err(|source| ConfigError::Syntax {
+                path: path.clone(),
+                source: Box::new(source),
+            })?
+        } else {
+            RiskState::default()
+        };
+        info!("risk_config: {:?}, risk_state: {:?}", config, state);
+        Ok(RiskManager::with_state(config, state))
+    }
+
+    pub fn with_state(config: &RiskConfig, state: RiskState) -> RiskManager {
+        RiskManager {
+            config: config.clone(),
+            state,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.state.halted
+    }
+
+    pub fn halt_reason(&self) -> Option<&String> {
+        self.state.halt_reason.as_ref()
+    }
+
+    /// Called on each live cycle with the equity, the usd balance plus the unrealized profit of
+    /// the open trades. Returns false once a loss limit is breached, the caller is expected to
+    /// flatten the open trades and stop trading.
+    pub fn check(&mut self, equity: f64) -> bool {
+        self.check_on(equity, Utc::now().format("%Y-%m-%d").to_string())
+    }
+
+    // `check` on the UTC day `today`
+    fn check_on(&mut self, equity: f64, today: String) -> bool {
+        if self.state.halted {
+            return false;
+        }
+        if self.state.day != today {
+            self.state.day = today;
+            self.state.day_start_usd = equity;
+        }
+        self.state.peak_usd = self.state.peak_usd.max(equity);
+
+        if let Some(max_daily_loss) = self.config.max_daily_loss {
+            let daily_loss = (self.state.day_start_usd - equity) / self.state.day_start_usd;
+            if daily_loss >= max_daily_loss {
+                self.halt(format!(
+                    "daily loss {:.4} >= max_daily_loss {:.4}",
+                    daily_loss, max_daily_loss
+                ));
+                return false;
+            }
+        }
+        if let Some(max_drawdown) = self.config.max_drawdown {
+            let drawdown = (self.state.peak_usd - equity) / self.state.peak_usd;
+            if drawdown >= max_drawdown {
+                self.halt(format!(
+                    "drawdown {:.4} >= max_drawdown {:.4}",
+                    drawdown, max_drawdown
+                ));
+                return false;
+            }
+        }
+        self.save();
+        true
+    }
+
+    /// Called before placing an entry order.
+    pub fn allow_entry(&self, trades: &[ManagedTrade], trade: &ManagedTrade) -> bool {
+        if self.state.halted {
+            warn!("Risk manager is halted, skip entry: {:?}", trade);
+            return false;
+        }
+        if let Some(max_open_trades) = self.config.max_open_trades {
+            if trades.len() >= max_open_trades {
+                warn!(
+                    "Open trades {} reach max_open_trades {}, skip entry",
+                    trades.len(),
+                    max_open_trades
+                );
+                return false;
+            }
+        }
+        if let Some(max_position_notional) = self.config.max_position_notional {
+            let notional: f64 = trades
+                .iter()
+                .map(|trade| trade.entry_price * trade.position)
+                .sum::<f64>()
+                + trade.entry_price * trade.position;
+            if notional > max_position_notional {
+                warn!(
+                    "Position notional {:.4} exceeds max_position_notional {:.4}, skip entry",
+                    notional, max_position_notional
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn halt(&mut self, reason: String) {
+        error!("Risk limit breached, halt trading: {}", reason);
+        self.state.halted = true;
+        self.state.halt_reason = Some(reason);
+        self.save();
+    }
+
+    // A failed save is logged, the limits still hold for this session
+    fn save(&self) {
+        let saved = File::create(&self.config.state_path).and_then(|file| {
+            serde_json::to_writer_pretty(file, &self.state).map_err(io::Error::from)
+        });
+        if let Err(err) = saved {
+            error!(
+                "Save risk state to {} error, {}",
+                self.config.state_path, err
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use trade_utils::types::trade::{Trade, TradeSide};
+
+    use super::*;
+
+    fn manager(config: RiskConfig) -> RiskManager {
+        RiskManager::with_state(&config, RiskState::default())
+    }
+
+    fn trade(entry_price: f64, position: f64) -> ManagedTrade {
+        ManagedTrade::new(Trade {
+            symbol: "BTCUSDT".to_owned(),
+            entry_price,
+            entry_side: TradeSide::Buy,
+            entry_ts: 0,
+            tp_price: entry_price * 1.1,
+            sl_price: entry_price * 0.9,
+            position,
+            exit_price: -1.,
+        })
+    }
+
+    fn state_path(dir: &tempfile::TempDir) -> String {
+        dir.path()
+            .join("risk_state.json")
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn halts_on_daily_loss() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(RiskConfig {
+            max_daily_loss: Some(0.05),
+            state_path: state_path(&dir),
+            ..Default::default()
+        });
+        assert!(manager.check_on(1000., "2024-01-01".to_owned()));
+        assert!(manager.check_on(960., "2024-01-01".to_owned()));
+        assert!(!manager.check_on(950., "2024-01-01".to_owned()));
+        assert!(manager.is_halted());
+        assert!(manager.halt_reason().unwrap().starts_with("daily loss"));
+        // Stays halted after a recovery
+        assert!(!manager.check_on(1100., "2024-01-02".to_owned()));
+    }
+
+    #[test]
+    fn daily_loss_restarts_each_day() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(RiskConfig {
+            max_daily_loss: Some(0.05),
+            state_path: state_path(&dir),
+            ..Default::default()
+        });
+        assert!(manager.check_on(1000., "2024-01-01".to_owned()));
+        assert!(manager.check_on(960., "2024-01-01".to_owned()));
+        // 7% below the first day, 3% below the second
+        assert!(manager.check_on(960., "2024-01-02".to_owned()));
+        assert!(manager.check_on(930., "2024-01-02".to_owned()));
+        assert!(!manager.is_halted());
+    }
+
+    #[test]
+    fn halts_on_drawdown_from_the_peak() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manager = manager(RiskConfig {
+            max_drawdown: Some(0.2),
+            state_path: state_path(&dir),
+            ..Default::default()
+        });
+        assert!(manager.check_on(1000., "2024-01-01".to_owned()));
+        assert!(manager.check_on(1200., "2024-01-02".to_owned()));
+        assert!(manager.check_on(970., "2024-01-03".to_owned()));
+        assert!(!manager.check_on(960., "2024-01-04".to_owned()));
+        assert!(manager.halt_reason().unwrap().starts_with("drawdown"));
+    }
+
+    #[test]
+    fn halt_persists_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RiskConfig {
+            max_drawdown: Some(0.1),
+            state_path: state_path(&dir),
+            ..Default::default()
+        };
+        let mut manager = RiskManager::new(&config).unwrap();
+        assert!(!manager.is_halted());
+        assert!(manager.check_on(1000., "2024-01-01".to_owned()));
+        assert!(!manager.check_on(850., "2024-01-01".to_owned()));
+        let restarted = RiskManager::new(&config).unwrap();
+        assert!(restarted.is_halted());
+        assert_eq!(restarted.halt_reason(), manager.halt_reason());
+    }
+
+    #[test]
+    fn corrupt_state_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = RiskConfig {
+            state_path: state_path(&dir),
+            ..Default::default()
+        };
+        std::fs::write(&config.state_path, "{\"halted\": tru").unwrap();
+        assert!(matches!(
+            RiskManager::new(&config),
+            Err(ConfigError::Syntax { .. })
+        ));
+    }
+
+    #[test]
+    fn allow_entry_limits_open_trades_and_notional() {
+        let manager_of = |config| manager(config);
+        let open = vec![trade(100., 10.)];
+        let max_open = manager_of(RiskConfig {
+            max_open_trades: Some(1),
+            ..Default::default()
+        });
+        assert!(!max_open.allow_entry(&open, &trade(100., 1.)));
+        assert!(max_open.allow_entry(&[], &trade(100., 1.)));
+        let max_notional = manager_of(RiskConfig {
+            max_position_notional: Some(1500.),
+            ..Default::default()
+        });
+        assert!(max_notional.allow_entry(&open, &trade(100., 5.)));
+        assert!(!max_notional.allow_entry(&open, &trade(100., 6.)));
+    }
+}
@@ -0,0 +1,163 @@
+use std::{env, fmt, path::Path};
+
+use serde::Deserialize;
+use trade_utils::clients::binance::api::BinanceFuturesApiClient;
+
+use crate::config::{read_config, ConfigError};
+
+pub const API_KEY_ENV: &str = "MOMENTUM_API_KEY";
+pub const SECRET_KEY_ENV: &str = "MOMENTUM_SECRET_KEY";
+
+/// Credential value, redacted in Debug so it never ends up in the logs.
+#[derive(Deserialize, Clone)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Credentials {
+    pub api_key: Secret,
+    pub secret_key: Secret,
+}
+
+impl Credentials {
+    pub fn api_client(&self) -> BinanceFuturesApiClient {
+        BinanceFuturesApiClient::new(
+            self.api_key.expose().to_owned(),
+            self.secret_key.expose().to_owned(),
+        )
+    }
+}
+
+/// Client for the public endpoints like klines, which need no credentials.
+pub fn public_api_client() -> BinanceFuturesApiClient {
+    BinanceFuturesApiClient::new(String::new(), String::new())
+}
+
+/// Credentials from MOMENTUM_API_KEY and MOMENTUM_SECRET_KEY, otherwise from the secrets file,
+/// which must not be accessible by group or others.
+pub fn load_credentials(secrets_path: &Path) -> Result<Credentials, ConfigError> {
+    if let (Ok(api_key), Ok(secret_key)) = (env::var(API_KEY_ENV), env::var(SECRET_KEY_ENV)) {
+        return Ok(Credentials {
+            api_key: Secret(api_key),
+            secret_key: Secret(secret_key),
+        });
+    }
+    if !secrets_path.exists() {
+        return Err(ConfigError::MissingCredentials {
+            path: secrets_path.to_owned(),
+        });
+    }
+    check_permissions(secrets_path)?;
+    read_config(secrets_path)
+}
+
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), ConfigError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let metadata = path.metadata().map_err(|source| ConfigError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mode = metadata.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(ConfigError::Invalid {
+            path: path.to_owned(),
+            field: "permissions".to_owned(),
+            reason: format!("must be 600, got {:o}", mode & 0o777),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), ConfigError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn write_secrets(dir: &Path, mode: u32) -> std::path::PathBuf {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let path = dir.join("secrets.json");
+        fs::write(
+            &path,
+            r#"{"api_key": "file_api_key", "secret_key": "file_secret_key"}"#,
+        )
+        .unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secrets_file_must_be_private() {
+        let dir = tempfile::tempdir().unwrap();
+        for mode in [0o600, 0o400, 0o700] {
+            let path = write_secrets(dir.path(), mode);
+            assert!(check_permissions(&path).is_ok(), "{:o}", mode);
+        }
+        for mode in [0o640, 0o604, 0o660, 0o644, 0o610] {
+            let path = write_secrets(dir.path(), mode);
+            assert!(
+                matches!(
+                    check_permissions(&path),
+                    Err(ConfigError::Invalid { field, .. }) if field == "permissions"
+                ),
+                "{:o}",
+                mode
+            );
+        }
+    }
+
+    // The only test touching the credential variables, so no other test races with it
+    #[cfg(unix)]
+    #[test]
+    fn environment_takes_precedence_over_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_secrets(dir.path(), 0o600);
+        env::set_var(API_KEY_ENV, "env_api_key");
+        env::set_var(SECRET_KEY_ENV, "env_secret_key");
+        let credentials = load_credentials(&path).unwrap();
+        assert_eq!(credentials.api_key.expose(), "env_api_key");
+        assert_eq!(credentials.secret_key.expose(), "env_secret_key");
+        // Both are needed, a lone key falls back to the file
+        env::remove_var(SECRET_KEY_ENV);
+        let credentials = load_credentials(&path).unwrap();
+        assert_eq!(credentials.api_key.expose(), "file_api_key");
+        assert_eq!(credentials.secret_key.expose(), "file_secret_key");
+        env::remove_var(API_KEY_ENV);
+        assert!(matches!(
+            load_credentials(&dir.path().join("missing.json")),
+            Err(ConfigError::MissingCredentials { .. })
+        ));
+    }
+
+    #[test]
+    fn debug_redacts_the_secrets() {
+        let credentials = Credentials {
+            api_key: Secret("plain_api_key".to_owned()),
+            secret_key: Secret("plain_secret_key".to_owned()),
+        };
+        let debug = format!("{:?}", credentials);
+        assert!(!debug.contains("plain_api_key"));
+        assert!(!debug.contains("plain_secret_key"));
+        assert!(debug.contains("Secret(***)"));
+    }
+}
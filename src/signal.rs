@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+use trade_utils::types::{kline::Kline, trade::TradeSide};
+
+use crate::{indicators::Ema, types::BacktestConfig};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SignalType {
+    #[default]
+    Difference, // close[i] - close[i - look_back]
+    RateOfChange, // (close[i] - close[i - look_back]) / close[i - look_back]
+    LogReturn,    // ln(close[i] / close[i - look_back])
+}
+
+/// Streaming momentum of the closed klines, backtest and live trade feed it the same way.
+pub struct MomentumSignal {
+    signal_type: SignalType,
+    look_back: usize,
+    closes: VecDeque<f64>,
+    ema: Option<Ema>, // Smoothing of the momentum
+    momentum: VecDeque<f64>,
+}
+
+impl MomentumSignal {
+    pub fn new(config: &BacktestConfig) -> MomentumSignal {
+        MomentumSignal {
+            signal_type: config.signal_type.clone(),
+            look_back: config.look_back_count,
+            closes: VecDeque::new(),
+            ema: if config.signal_smoothing > 1 {
+                Some(Ema::new(config.signal_smoothing))
+            } else {
+                None
+            },
+            momentum: VecDeque::new(),
+        }
+    }
+
+    pub fn update(&mut self, kline: &Kline) -> Option<f64> {
+        self.closes.push_back(kline.close);
+        // As in the original backtest, the first momentum is on kline look_back + 1
+        if self.closes.len() <= self.look_back + 1 {
+            return None;
+        }
+        if self.closes.len() > self.look_back + 2 {
+            self.closes.pop_front();
+        }
+        let prev_close = self.closes[1];
+        let curr_close = kline.close;
+        let raw_momentum = match self.signal_type {
+            SignalType::Difference => curr_close - prev_close,
+            SignalType::RateOfChange => (curr_close - prev_close) / prev_close,
+            SignalType::LogReturn => (curr_close / prev_close).ln(),
+        };
+        let momentum = match self.ema.as_mut() {
+            Some(ema) => ema.update_value(raw_momentum)?,
+            None => raw_momentum,
+        };
+        self.momentum.push_back(momentum);
+        if self.momentum.len() > self.look_back {
+            self.momentum.pop_front();
+        }
+        Some(momentum)
+    }
+
+    pub fn momentum(&self) -> &VecDeque<f64> {
+        &self.momentum
+    }
+}
+
+/// Buy when the momentum crosses above `signal_threshold`, sell when it crosses below
+/// `-signal_threshold`, a zero threshold is the plain sign flip.
+pub fn entry_side(config: &BacktestConfig, momentum: &VecDeque<f64>, kline: &Kline) -> TradeSide {
+    if momentum.len() < 2 {
+        return TradeSide::None;
+    }
+    let prev = momentum[momentum.len() - 2];
+    let curr = momentum[momentum.len() - 1];
+    let threshold = config.signal_threshold;
+    let uptrend = kline.close > kline.open;
+
+    if prev < threshold && curr >= threshold && (uptrend || !config.candle_filter) {
+        TradeSide::Buy
+    } else if prev >= -threshold && curr < -threshold && (!uptrend || !config.candle_filter) {
+        TradeSide::Sell
+    } else {
+        TradeSide::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    // The base config with `fields` replaced
+    fn config(fields: Value) -> BacktestConfig {
+        let mut value = json!({
+            "initial_captial": 1000.0,
+            "fee_rate": 0.0004,
+            "entry_portion": 0.5,
+            "look_back_count": 2,
+            "risk_portion": 0.05,
+            "tp_ratio": 2.0,
+            "candle_filter": false
+        });
+        for (key, field) in fields.as_object().unwrap() {
+            value[key] = field.clone();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn kline(open: f64, close: f64) -> Kline {
+        Kline {
+            open_timestamp: 0,
+            close_timestamp: 0,
+            open,
+            high: open.max(close),
+            low: open.min(close),
+            close,
+            volume: 1.,
+        }
+    }
+
+    fn side(config: &BacktestConfig, momentum: [f64; 2], kline: &Kline) -> TradeSide {
+        entry_side(config, &VecDeque::from(momentum.to_vec()), kline)
+    }
+
+    #[test]
+    fn momentum_of_each_signal_type() {
+        let closes = [100., 100., 110., 121.];
+        for (signal_type, expected) in [
+            ("difference", 21.),
+            ("rate_of_change", 0.21),
+            ("log_return", 1.21f64.ln()),
+        ] {
+            let mut signal = MomentumSignal::new(&config(json!({"signal_type": signal_type})));
+            let momentum: Vec<Option<f64>> = closes
+                .iter()
+                .map(|&close| signal.update(&kline(close, close)))
+                .collect();
+            // The first momentum is on kline look_back_count + 2
+            assert_eq!(momentum[..3], [None, None, None]);
+            assert!((momentum[3].unwrap() - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn entry_on_a_crossing_of_the_threshold() {
+        let up = kline(100., 101.);
+        let plain = config(json!({}));
+        assert_eq!(side(&plain, [-1., 1.], &up), TradeSide::Buy);
+        assert_eq!(side(&plain, [1., -1.], &up), TradeSide::Sell);
+        assert_eq!(side(&plain, [1., 2.], &up), TradeSide::None);
+        let threshold = config(json!({"signal_threshold": 2.0}));
+        assert_eq!(side(&threshold, [-1., 1.], &up), TradeSide::None);
+        assert_eq!(side(&threshold, [1., 2.], &up), TradeSide::Buy);
+        assert_eq!(side(&threshold, [-1., -3.], &up), TradeSide::Sell);
+    }
+
+    #[test]
+    fn candle_filter_needs_a_candle_of_the_side() {
+        let config = config(json!({"candle_filter": true}));
+        let (up, down) = (kline(100., 101.), kline(101., 100.));
+        assert_eq!(side(&config, [-1., 1.], &up), TradeSide::Buy);
+        assert_eq!(side(&config, [-1., 1.], &down), TradeSide::None);
+        assert_eq!(side(&config, [1., -1.], &down), TradeSide::Sell);
+        assert_eq!(side(&config, [1., -1.], &up), TradeSide::None);
+    }
+}
@@ -1,7 +1,7 @@
 use std::{collections::VecDeque, fs::OpenOptions};
 
 use async_std::task;
-use chrono::NaiveDateTime;
+use chrono::DateTime;
 use log::*;
 use trade_utils::{
     clients::binance::api::{BinanceFuturesApiClient, SYMBOL_TO_INSTRUMENT_INFO},
@@ -12,7 +12,16 @@ use trade_utils::{
     },
 };
 
-use crate::{backtest::BacktestMetric, types::BacktestConfig};
+#[cfg(feature = "exchange-orders")]
+use crate::orders::place_limit_entry;
+use crate::{
+    backtest::{BacktestMetric, TradeRecord},
+    execution::{entry_fill, fill_price, market_price},
+    filter::EntryFilters,
+    risk::RiskManager,
+    signal::entry_side,
+    types::{BacktestConfig, EntryOrder, ManagedTrade, Pyramiding},
+};
 
 pub fn place_order(
     symbol: String,
@@ -50,59 +59,67 @@ pub fn open_trade(
     symbol: String,
     metric: &mut BacktestMetric,
     config: &BacktestConfig,
-    trades: &mut Vec<Trade>,
+    trades: &mut Vec<ManagedTrade>,
     momentum: &VecDeque<f64>,
+    filters: &EntryFilters,
     output_trade_log: bool,
     output_trade_log_name: &str,
     kline: &Kline,
+    risk_manager_opt: Option<&RiskManager>,
     api_client_opt: Option<&BinanceFuturesApiClient>,
 ) {
-    let prev_sign = momentum[momentum.len() - 2].signum();
-    let curr_sign = momentum[momentum.len() - 1].signum();
-    let uptrend = kline.close > kline.open;
+    let side = entry_side(config, momentum, kline);
+    let output = ExitOutput {
+        output_trade_log,
+        output_trade_log_name,
+        api_client_opt,
+    };
 
-    if let Some(api_client) = api_client_opt {
-        let account = task::block_on(api_client.get_account()).unwrap();
-        let usd_balance = account.get_usd_balance(); // Correct the usd_balance during live trade
-        metric.usd_balance = usd_balance;
-    }
-
-    if prev_sign == -1. && curr_sign == 1. && uptrend {
+    if side == TradeSide::Buy {
         // Close sell trades
-        trades.retain_mut(|trade: &mut Trade| {
+        trades.retain_mut(|trade: &mut ManagedTrade| {
             if trade.entry_side == TradeSide::Sell {
-                let profit = (trade.entry_price - kline.close) * trade.position;
-                metric.usd_balance += profit;
-                metric.fee = kline.close * trade.position * config.fee_rate;
-                metric.total_fee += metric.fee;
-                metric.profit = profit;
-                metric.total_profit += profit;
-                trade.exit_price = kline.close;
                 error!("Early exit sell");
-                trade_log(
+                close_trade(
                     metric,
                     config,
-                    output_trade_log,
-                    output_trade_log_name,
                     kline,
                     trade,
+                    market_price(metric, config, kline),
+                    output,
                 );
-                place_order(trade.symbol.clone(), api_client_opt, trade, true);
                 false
             } else {
                 true
             }
         });
 
-        let entry_price = kline.close;
         let entry_side = TradeSide::Buy;
+        if !config.direction.allows(&entry_side) || !filters.allows(&entry_side, kline) {
+            return;
+        }
+        let entry_portion = match pyramiding_entry_portion(config, trades, &entry_side) {
+            Some(entry_portion) => entry_portion,
+            None => return,
+        };
+        let entry_fill = match entry_fill(
+            metric,
+            config,
+            true,
+            metric.usd_balance * entry_portion / kline.close,
+            kline,
+        ) {
+            Some(entry_fill) => entry_fill,
+            None => return,
+        };
+        let entry_price = entry_fill.price;
         let mut sl_price_diff = f64::abs(kline.close - kline.low);
         if sl_price_diff / kline.close > config.risk_portion {
             sl_price_diff = kline.close * config.risk_portion;
         }
         let sl_price = entry_price - sl_price_diff;
-        let tp_price = entry_price + config.tp_ratio * sl_price_diff;
-        let position = metric.usd_balance * config.entry_portion / entry_price;
+        let tp_price = entry_price + tp_r_multiple(config, 0) * sl_price_diff;
+        let position = metric.usd_balance * entry_portion / entry_price;
         let entry_ts = kline.close_timestamp;
         let trade = Trade {
             symbol: symbol.clone(),
@@ -114,46 +131,60 @@ pub fn open_trade(
             position,
             exit_price: -1.,
         };
-        place_order(trade.symbol.clone(), api_client_opt, &trade, false);
-        trades.push(trade);
-        metric.fee = entry_price * position * config.fee_rate;
-        metric.total_fee += metric.fee;
-    } else if prev_sign == 1. && curr_sign == -1. && !uptrend {
+        enter_trade(
+            metric,
+            config,
+            trades,
+            ManagedTrade::new(trade),
+            entry_fill.fee_rate,
+            risk_manager_opt,
+            api_client_opt,
+        );
+    } else if side == TradeSide::Sell {
         // Close buy trades
-        trades.retain_mut(|trade: &mut Trade| {
+        trades.retain_mut(|trade: &mut ManagedTrade| {
             if trade.entry_side == TradeSide::Buy {
-                let profit = (kline.close - trade.entry_price) * trade.position;
-                metric.usd_balance += profit;
-                metric.fee = kline.close * trade.position * config.fee_rate;
-                metric.total_fee += metric.fee;
-                metric.profit = profit;
-                metric.total_profit += profit;
-                trade.exit_price = kline.close;
                 error!("Early exit buy");
-                trade_log(
+                close_trade(
                     metric,
                     config,
-                    output_trade_log,
-                    output_trade_log_name,
                     kline,
                     trade,
+                    market_price(metric, config, kline),
+                    output,
                 );
-                place_order(trade.symbol.clone(), api_client_opt, &trade, true);
                 false
             } else {
                 true
             }
         });
 
-        let entry_price = kline.close;
         let entry_side = TradeSide::Sell;
+        if !config.direction.allows(&entry_side) || !filters.allows(&entry_side, kline) {
+            return;
+        }
+        let entry_portion = match pyramiding_entry_portion(config, trades, &entry_side) {
+            Some(entry_portion) => entry_portion,
+            None => return,
+        };
+        let entry_fill = match entry_fill(
+            metric,
+            config,
+            false,
+            metric.usd_balance * entry_portion / kline.close,
+            kline,
+        ) {
+            Some(entry_fill) => entry_fill,
+            None => return,
+        };
+        let entry_price = entry_fill.price;
         let mut sl_price_diff = f64::abs(kline.close - kline.high);
         if sl_price_diff / kline.close > config.risk_portion {
             sl_price_diff = kline.close * config.risk_portion;
         }
         let sl_price = entry_price + sl_price_diff;
-        let tp_price = entry_price - config.tp_ratio * sl_price_diff;
-        let position = metric.usd_balance * config.entry_portion / entry_price;
+        let tp_price = entry_price - tp_r_multiple(config, 0) * sl_price_diff;
+        let position = metric.usd_balance * entry_portion / entry_price;
         let entry_ts = kline.close_timestamp;
         let trade = Trade {
             symbol: symbol.clone(),
@@ -165,104 +196,324 @@ pub fn open_trade(
             position,
             exit_price: -1.,
         };
-        place_order(trade.symbol.clone(), api_client_opt, &trade, false);
-        trades.push(trade);
-        metric.fee = entry_price * position * config.fee_rate;
-        metric.total_fee += metric.fee;
+        enter_trade(
+            metric,
+            config,
+            trades,
+            ManagedTrade::new(trade),
+            entry_fill.fee_rate,
+            risk_manager_opt,
+            api_client_opt,
+        );
+    }
+}
+
+/// Returns the entry portion allowed by the pyramiding policy, None if the entry should be skipped.
+pub fn pyramiding_entry_portion(
+    config: &BacktestConfig,
+    trades: &[ManagedTrade],
+    entry_side: &TradeSide,
+) -> Option<f64> {
+    let entries = trades
+        .iter()
+        .filter(|trade| trade.entry_side == *entry_side)
+        .count();
+    match config.pyramiding {
+        Pyramiding::Unlimited => Some(config.entry_portion),
+        Pyramiding::Single if entries > 0 => None,
+        Pyramiding::Single => Some(config.entry_portion),
+        Pyramiding::Stack if entries >= config.max_entries => None,
+        Pyramiding::Stack => Some(config.entry_portion),
+        Pyramiding::ScaleIn if entries >= config.max_entries => None,
+        Pyramiding::ScaleIn => {
+            Some(config.entry_portion * config.scale_in_decay.powi(entries as i32))
+        }
+    }
+}
+
+fn enter_trade(
+    metric: &mut BacktestMetric,
+    config: &BacktestConfig,
+    trades: &mut Vec<ManagedTrade>,
+    mut trade: ManagedTrade,
+    fee_rate: f64,
+    risk_manager_opt: Option<&RiskManager>,
+    api_client_opt: Option<&BinanceFuturesApiClient>,
+) {
+    if let Some(risk_manager) = risk_manager_opt {
+        if !risk_manager.allow_entry(trades, &trade) {
+            return;
+        }
+    }
+    let live_fill = match (api_client_opt, &config.entry_order) {
+        #[cfg(feature = "exchange-orders")]
+        (Some(api_client), EntryOrder::PostOnly | EntryOrder::Ioc) => {
+            let live_fill = place_limit_entry(api_client, config, &trade);
+            metric.live_fills.push(live_fill);
+            Some(live_fill)
+        }
+        // Replay enters at the journaled fill, the backtest has none and uses the fill model
+        (None, EntryOrder::PostOnly | EntryOrder::Ioc) => metric.journal_fills.pop_front(),
+        _ => {
+            place_order(trade.symbol.clone(), api_client_opt, &trade, false);
+            None
+        }
+    };
+    if let Some(live_fill) = live_fill {
+        if live_fill.position <= 0. {
+            warn!("Limit entry {:?} is not filled", trade.entry_side);
+            return;
+        }
+        trade.fill(live_fill.position, live_fill.entry_price);
+    }
+    metric.fee = trade.entry_price * trade.position * fee_rate;
+    metric.total_fee += metric.fee;
+    metric.usd_balance -= metric.fee;
+    trade.entry_fee = metric.fee;
+    let fee = metric.fee;
+    metric.side_mut(&trade.entry_side).total_fee += fee;
+    trades.push(trade);
+}
+
+/// Trade log and exchange an exit goes to, without an api client the exit places no order.
+#[derive(Clone, Copy)]
+pub struct ExitOutput<'a> {
+    pub output_trade_log: bool,
+    pub output_trade_log_name: &'a str,
+    pub api_client_opt: Option<&'a BinanceFuturesApiClient>,
+}
+
+/// Books the profit and fee of the trade exited at `exit_price` and unwinds it on the exchange.
+pub fn close_trade(
+    metric: &mut BacktestMetric,
+    config: &BacktestConfig,
+    kline: &Kline,
+    trade: &mut ManagedTrade,
+    exit_price: f64,
+    output: ExitOutput,
+) {
+    let position = trade.position;
+    close_position(metric, config, kline, trade, exit_price, position, output);
+}
+
+/// Same as `close_trade` but only for `position` of the trade, the rest stays open.
+pub fn close_position(
+    metric: &mut BacktestMetric,
+    config: &BacktestConfig,
+    kline: &Kline,
+    trade: &mut ManagedTrade,
+    exit_price: f64,
+    position: f64,
+    output: ExitOutput,
+) {
+    let position = position.min(trade.position);
+    let partial = position < trade.position;
+    let exit_price = fill_price(
+        metric,
+        config,
+        trade.entry_side != TradeSide::Buy,
+        exit_price,
+        position,
+        kline,
+    );
+    let profit = if trade.entry_side == TradeSide::Buy {
+        (exit_price - trade.entry_price) * position
+    } else {
+        (trade.entry_price - exit_price) * position
+    };
+    metric.fee = exit_price * position * config.fee_rate;
+    metric.total_fee += metric.fee;
+    metric.usd_balance += profit - metric.fee;
+    metric.profit = profit;
+    metric.total_profit += profit;
+    trade.realized_profit += profit;
+
+    let mut exit_trade = trade.clone();
+    exit_trade.position = position;
+    exit_trade.exit_price = exit_price;
+    trade_log(
+        metric,
+        config,
+        output.output_trade_log,
+        output.output_trade_log_name,
+        kline,
+        &exit_trade,
+        partial,
+    );
+    place_order(
+        trade.symbol.clone(),
+        output.api_client_opt,
+        &exit_trade,
+        true,
+    );
+    trade.position -= position;
+    if !partial {
+        trade.exit_price = exit_price;
+    }
+}
+
+/// Take profit distance of the `index`-th level in R-multiples, `tp_ratio` without levels.
+pub fn tp_r_multiple(config: &BacktestConfig, index: usize) -> f64 {
+    config
+        .tp_levels
+        .get(index)
+        .map(|tp_level| tp_level.r_multiple)
+        .unwrap_or(config.tp_ratio)
+}
+
+/// Closes every open trade at the kline close, used when the risk manager halts trading.
+pub fn close_all_trades(
+    metric: &mut BacktestMetric,
+    config: &BacktestConfig,
+    trades: &mut Vec<ManagedTrade>,
+    kline: &Kline,
+    output: ExitOutput,
+) {
+    for trade in trades.iter_mut() {
+        error!("Flatten {:?}", trade.entry_side);
+        close_trade(
+            metric,
+            config,
+            kline,
+            trade,
+            market_price(metric, config, kline),
+            output,
+        );
+    }
+    trades.clear();
+}
+
+/// Moves the stop of an open trade by the trailing and break-even rules, the stop only ever
+/// moves in favor of the trade.
+pub fn update_stop(
+    config: &BacktestConfig,
+    trade: &mut ManagedTrade,
+    kline: &Kline,
+    atr: Option<f64>,
+) {
+    let is_buy = trade.entry_side == TradeSide::Buy;
+    let mut sl_prices = Vec::new();
+    if config.trailing_stop_pct > 0. {
+        if is_buy {
+            sl_prices.push(kline.close * (1. - config.trailing_stop_pct));
+        } else {
+            sl_prices.push(kline.close * (1. + config.trailing_stop_pct));
+        }
+    }
+    if let (true, Some(atr)) = (config.trailing_stop_atr > 0., atr) {
+        if is_buy {
+            sl_prices.push(kline.close - config.trailing_stop_atr * atr);
+        } else {
+            sl_prices.push(kline.close + config.trailing_stop_atr * atr);
+        }
+    }
+    if config.break_even_r > 0. {
+        let gain = if is_buy {
+            kline.close - trade.entry_price
+        } else {
+            trade.entry_price - kline.close
+        };
+        if gain >= config.break_even_r * trade.initial_risk() {
+            sl_prices.push(trade.entry_price);
+        }
+    }
+    for sl_price in sl_prices {
+        if is_buy && sl_price > trade.sl_price {
+            info!("Move buy sl_price {:.4} -> {:.4}", trade.sl_price, sl_price);
+            trade.sl_price = sl_price;
+        } else if !is_buy && sl_price < trade.sl_price {
+            info!(
+                "Move sell sl_price {:.4} -> {:.4}",
+                trade.sl_price, sl_price
+            );
+            trade.sl_price = sl_price;
+        }
     }
 }
 
 pub fn sl_tp_exit(
     metric: &mut BacktestMetric,
     config: &BacktestConfig,
-    trades: &mut Vec<Trade>,
-    output_trade_log: bool,
-    output_trade_log_name: &str,
+    trades: &mut Vec<ManagedTrade>,
     kline: &Kline,
-    api_client_opt: Option<&BinanceFuturesApiClient>,
+    atr: Option<f64>,
+    output: ExitOutput,
 ) {
-    trades.retain_mut(|trade: &mut Trade| {
-        if trade.entry_side == TradeSide::Buy {
-            if kline.close <= trade.sl_price {
-                let profit = (kline.close - trade.entry_price) * trade.position;
-                metric.usd_balance += profit;
-                metric.fee = kline.close * trade.position * config.fee_rate;
-                metric.total_fee += metric.fee;
-                metric.profit = profit;
-                metric.total_profit += profit;
-                trade.exit_price = kline.close;
-                trade_log(
-                    metric,
-                    config,
-                    output_trade_log,
-                    output_trade_log_name,
-                    kline,
-                    trade,
-                );
-                place_order(trade.symbol.clone(), api_client_opt, &trade, true);
-                false
-            } else if kline.close >= trade.tp_price {
-                let profit = (kline.close - trade.entry_price) * trade.position;
-                metric.usd_balance += profit;
-                metric.fee = kline.close * trade.position * config.fee_rate;
-                metric.total_fee += metric.fee;
-                metric.profit = profit;
-                metric.total_profit += profit;
-                trade.exit_price = kline.close;
-                trade_log(
-                    metric,
-                    config,
-                    output_trade_log,
-                    output_trade_log_name,
-                    kline,
-                    trade,
-                );
-                place_order(trade.symbol.clone(), api_client_opt, &trade, true);
-                false
+    trades.retain_mut(|trade: &mut ManagedTrade| {
+        if trade.entry_side == TradeSide::None {
+            return true;
+        }
+        let is_buy = trade.entry_side == TradeSide::Buy;
+        trade.bars_held += 1;
+
+        // A mirrored stop order fills intrabar on the exchange, so there is nothing left to unwind
+        if config.mirror_stop_orders
+            && !trade.stop_order_missing
+            && ((is_buy && kline.low <= trade.sl_price)
+                || (!is_buy && kline.high >= trade.sl_price))
+        {
+            let exit_price = if is_buy {
+                trade.sl_price.min(kline.open)
             } else {
-                true
+                trade.sl_price.max(kline.open)
+            };
+            let output = ExitOutput {
+                api_client_opt: None,
+                ..output
+            };
+            close_trade(metric, config, kline, trade, exit_price, output);
+            return false;
+        }
+
+        let sl_hit =
+            (is_buy && kline.close <= trade.sl_price) || (!is_buy && kline.close >= trade.sl_price);
+        let tp_reached = |tp_price: f64| {
+            (is_buy && kline.close >= tp_price) || (!is_buy && kline.close <= tp_price)
+        };
+        let mut tp_hit = tp_reached(trade.tp_price);
+        let time_exit = config.max_holding_bars > 0 && trade.bars_held >= config.max_holding_bars;
+        // A close beyond several levels takes all of them on the bar
+        while tp_hit && !sl_hit && trade.tp_level_index + 1 < config.tp_levels.len() {
+            // Take the fraction of the level and aim at the next level with the rest
+            let tp_level = &config.tp_levels[trade.tp_level_index];
+            let position = trade.initial_position() * tp_level.fraction;
+            error!("Take profit level {}", trade.tp_level_index);
+            close_position(
+                metric,
+                config,
+                kline,
+                trade,
+                market_price(metric, config, kline),
+                position,
+                output,
+            );
+            if trade.position <= 0. {
+                return false;
             }
-        } else if trade.entry_side == TradeSide::Sell {
-            if kline.close >= trade.sl_price {
-                let profit = (trade.entry_price - kline.close) * trade.position;
-                metric.usd_balance += profit;
-                metric.fee = kline.close * trade.position * config.fee_rate;
-                metric.total_fee += metric.fee;
-                metric.profit = profit;
-                metric.total_profit += profit;
-                trade.exit_price = kline.close;
-                trade_log(
-                    metric,
-                    config,
-                    output_trade_log,
-                    output_trade_log_name,
-                    kline,
-                    trade,
-                );
-                place_order(trade.symbol.clone(), api_client_opt, &trade, true);
-                false
-            } else if kline.close <= trade.tp_price {
-                let profit = (trade.entry_price - kline.close) * trade.position;
-                metric.usd_balance += profit;
-                metric.fee = kline.close * trade.position * config.fee_rate;
-                metric.total_fee += metric.fee;
-                metric.profit = profit;
-                metric.total_profit += profit;
-                trade.exit_price = kline.close;
-                trade_log(
-                    metric,
-                    config,
-                    output_trade_log,
-                    output_trade_log_name,
-                    kline,
-                    trade,
-                );
-                place_order(trade.symbol.clone(), api_client_opt, &trade, true);
-                false
+            trade.tp_level_index += 1;
+            let tp_price_diff = tp_r_multiple(config, trade.tp_level_index) * trade.initial_risk();
+            trade.tp_price = if is_buy {
+                trade.entry_price + tp_price_diff
             } else {
-                true
+                trade.entry_price - tp_price_diff
+            };
+            tp_hit = tp_reached(trade.tp_price);
+        }
+        if sl_hit || tp_hit || time_exit {
+            if time_exit && !sl_hit && !tp_hit {
+                error!("Time exit after {} bars", trade.bars_held);
             }
+            close_trade(
+                metric,
+                config,
+                kline,
+                trade,
+                market_price(metric, config, kline),
+                output,
+            );
+            false
         } else {
+            update_stop(config, trade, kline, atr);
             true
         }
     });
@@ -274,10 +525,15 @@ pub fn trade_log(
     output_trade_log: bool,
     output_trade_log_name: &str,
     kline: &Kline,
-    trade: &Trade,
+    trade: &ManagedTrade,
+    partial: bool,
 ) {
-    let curr_date = NaiveDateTime::from_timestamp_millis(kline.close_timestamp).unwrap();
-    let entry_date = NaiveDateTime::from_timestamp_millis(trade.entry_ts).unwrap();
+    let curr_date = DateTime::from_timestamp_millis(kline.close_timestamp)
+        .unwrap()
+        .naive_utc();
+    let entry_date = DateTime::from_timestamp_millis(trade.entry_ts)
+        .unwrap()
+        .naive_utc();
     metric.max_usd = metric.max_usd.max(metric.usd_balance);
     metric.min_usd = metric.min_usd.min(metric.usd_balance);
     let mut msg = "".to_string();
@@ -295,13 +551,36 @@ pub fn trade_log(
     msg += &format!("profit: {:.4}, ", metric.profit);
     msg += &format!("fee: {:.4}, ", metric.fee);
 
-    if metric.profit > 0. {
+    let fee = metric.fee;
+    let profit = metric.profit;
+    metric.side_mut(&trade.entry_side).record(profit, fee);
+    metric.trades.push(TradeRecord {
+        entry_ts: trade.entry_ts,
+        exit_ts: kline.close_timestamp,
+        entry_side: trade.entry_side.clone(),
+        entry_price: trade.entry_price,
+        exit_price: trade.exit_price,
+        position: trade.position,
+        profit,
+        fee,
+        entry_fee: trade.entry_fee * trade.position / trade.initial_position(),
+        partial,
+    });
+    if partial {
+        // Win or lose is counted once the whole trade is closed
+        metric.partial_exits += 1;
+        msg += &format!("realized_profit: {:.4}, ", trade.realized_profit);
+        msg += &format!("partial_exits: {:?}, ", metric.partial_exits);
+        info!("{}", msg);
+    } else if trade.realized_profit > 0. {
         metric.win += 1;
+        metric.side_mut(&trade.entry_side).win += 1;
         msg += &format!("win: {:?}, ", metric.win);
         msg += &format!("lose: {:?}, ", metric.lose);
         info!("{}", msg);
     } else {
         metric.lose += 1;
+        metric.side_mut(&trade.entry_side).lose += 1;
         msg += &format!("win: {:?}, ", metric.win);
         msg += &format!("lose: {:?}, ", metric.lose);
         warn!("{}", msg);
@@ -333,3 +612,153 @@ pub fn trade_log(
         writer.flush().unwrap();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    // The base config with `fields` replaced
+    fn config(fields: Value) -> BacktestConfig {
+        let mut value = json!({
+            "initial_captial": 1000.0,
+            "fee_rate": 0.0,
+            "entry_portion": 0.5,
+            "look_back_count": 2,
+            "risk_portion": 0.05,
+            "tp_ratio": 2.0,
+            "candle_filter": false
+        });
+        for (key, field) in fields.as_object().unwrap() {
+            value[key] = field.clone();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn trade(entry_side: TradeSide) -> ManagedTrade {
+        ManagedTrade::new(Trade {
+            symbol: "BTCUSDT".to_owned(),
+            entry_price: 100.,
+            entry_side,
+            entry_ts: 0,
+            tp_price: 110.,
+            sl_price: 95.,
+            position: 1.,
+            exit_price: -1.,
+        })
+    }
+
+    #[test]
+    fn pyramiding_limits_same_side_entries() {
+        let buys = [trade(TradeSide::Buy), trade(TradeSide::Buy)];
+        let portion = |pyramiding: &str, trades: &[ManagedTrade]| {
+            let config = config(json!({"pyramiding": pyramiding, "max_entries": 2}));
+            pyramiding_entry_portion(&config, trades, &TradeSide::Buy)
+        };
+        assert_eq!(portion("unlimited", &buys), Some(0.5));
+        assert_eq!(portion("single", &[]), Some(0.5));
+        assert_eq!(portion("single", &buys[..1]), None);
+        assert_eq!(portion("stack", &buys[..1]), Some(0.5));
+        assert_eq!(portion("stack", &buys), None);
+        // Entries of the other side do not count
+        assert_eq!(portion("single", &[trade(TradeSide::Sell)]), Some(0.5));
+    }
+
+    #[test]
+    fn scale_in_decays_the_entry_portion() {
+        let config = config(json!({
+            "pyramiding": "scale_in", "max_entries": 3, "scale_in_decay": 0.5
+        }));
+        let buys = [
+            trade(TradeSide::Buy),
+            trade(TradeSide::Buy),
+            trade(TradeSide::Buy),
+        ];
+        let portions: Vec<Option<f64>> = (0..=3)
+            .map(|entries| pyramiding_entry_portion(&config, &buys[..entries], &TradeSide::Buy))
+            .collect();
+        assert_eq!(portions, vec![Some(0.5), Some(0.25), Some(0.125), None]);
+    }
+
+    fn kline(close: f64) -> Kline {
+        Kline {
+            open_timestamp: 0,
+            close_timestamp: 86_399_999,
+            open: close,
+            high: close,
+            low: close,
+            close,
+            volume: 1.,
+        }
+    }
+
+    const OUTPUT: ExitOutput = ExitOutput {
+        output_trade_log: false,
+        output_trade_log_name: "",
+        api_client_opt: None,
+    };
+
+    #[test]
+    fn close_beyond_several_tp_levels_takes_them_all() {
+        let config = config(json!({"tp_levels": [
+            {"r_multiple": 1.0, "fraction": 0.5},
+            {"r_multiple": 2.0, "fraction": 0.25},
+            {"r_multiple": 3.0, "fraction": 0.25}
+        ]}));
+        let mut metric = BacktestMetric::new(&config);
+        // 1R is 5, the close of 111 is beyond the levels at 105 and 110 but not 115
+        let mut buy = trade(TradeSide::Buy);
+        buy.trade.tp_price = 105.;
+        let mut trades = vec![buy];
+        sl_tp_exit(
+            &mut metric,
+            &config,
+            &mut trades,
+            &kline(111.),
+            None,
+            OUTPUT,
+        );
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].tp_level_index, 2);
+        assert_eq!(trades[0].tp_price, 115.);
+        assert!((trades[0].position - 0.25).abs() < 1e-9);
+        let positions: Vec<f64> = metric.trades.iter().map(|record| record.position).collect();
+        assert_eq!(positions, vec![0.5, 0.25]);
+        // The last level takes the rest
+        sl_tp_exit(
+            &mut metric,
+            &config,
+            &mut trades,
+            &kline(116.),
+            None,
+            OUTPUT,
+        );
+        assert!(trades.is_empty());
+        assert!((metric.total_profit - (0.5 * 11. + 0.25 * 11. + 0.25 * 16.)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn long_only_exits_on_a_sell_signal_without_a_sell_entry() {
+        let config = config(json!({"direction": "long_only"}));
+        let mut metric = BacktestMetric::new(&config);
+        let mut trades = vec![trade(TradeSide::Buy)];
+        let momentum = VecDeque::from(vec![1., -1.]);
+        open_trade(
+            "BTCUSDT".to_owned(),
+            &mut metric,
+            &config,
+            &mut trades,
+            &momentum,
+            &EntryFilters::new(&config),
+            false,
+            "",
+            &kline(98.),
+            None,
+            None,
+        );
+        assert!(trades.is_empty());
+        assert_eq!(metric.trades.len(), 1);
+        assert_eq!(metric.trades[0].entry_side, TradeSide::Buy);
+    }
+}
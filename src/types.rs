@@ -1,39 +1,524 @@
+use std::ops::{Deref, DerefMut};
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
-use trade_utils::types::cli::Mode;
+use trade_utils::types::trade::{Trade, TradeSide};
+
+use crate::{
+    analysis::{BasketObjective, Objective},
+    cache::CACHE_PATH,
+    filter::FilterConfig,
+    monte_carlo::Resampling,
+    risk::RiskConfig,
+    secrets::Secret,
+    signal::SignalType,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BacktestConfig {
     pub initial_captial: f64,
     pub fee_rate: f64,
     pub entry_portion: f64,
-    pub look_back_count: f64, // f64 is for hypertune
+    pub look_back_count: usize,
     pub risk_portion: f64,
     pub tp_ratio: f64,
+    #[serde(default)]
+    pub signal_type: SignalType,
+    #[serde(default)]
+    pub signal_smoothing: usize, // EMA period of the momentum, 0 disables it
+    #[serde(default)]
+    pub signal_threshold: f64, // Band around zero the momentum has to cross, in signal_type units
+    #[serde(default = "default_candle_filter")]
+    pub candle_filter: bool, // Buy on up candles and sell on down candles only
+    #[serde(default)]
+    pub filters: Vec<FilterConfig>,
+    #[serde(default)]
+    pub regime_ema_period: usize, // EMA period in regime bars, 0 disables it
+    #[serde(default = "default_regime_bars")]
+    pub regime_bars: usize, // Klines per regime bar, e.g. 7 for weekly
+    #[serde(default = "default_regime_slope_bars")]
+    pub regime_slope_bars: usize, // Regime bars of the EMA slope
+    #[serde(default)]
+    pub regime_collection: Option<String>, // e.g. BTCUSDT_1d, None uses the traded klines
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub pyramiding: Pyramiding,
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize, // Max same-side entries for stack and scale_in
+    #[serde(default = "default_scale_in_decay")]
+    pub scale_in_decay: f64, // Entry portion multiplier per stacked entry for scale_in
+    #[serde(default)]
+    pub trailing_stop_pct: f64, // 0 disables the trailing stop by percentage
+    #[serde(default)]
+    pub trailing_stop_atr: f64, // ATR multiple, 0 disables the trailing stop by ATR
+    #[serde(default = "default_atr_period")]
+    pub atr_period: usize,
+    #[serde(default)]
+    pub break_even_r: f64, // R-multiple to move the stop to break-even, 0 disables it
+    #[serde(default)]
+    pub max_holding_bars: usize, // 0 disables the time-based exit
+    #[serde(default)]
+    pub mirror_stop_orders: bool, // Exchange stop orders during live trade, the backtest stops intrabar
+    #[serde(default)]
+    pub tp_levels: Vec<TpLevel>, // Replaces tp_ratio when not empty, the last level takes the rest
+    #[serde(default)]
+    pub fill_timing: FillTiming, // When backtest market orders decided on the close fill
+    #[serde(default)]
+    pub slippage_bps: f64, // Adverse slippage of every backtest fill
+    #[serde(default)]
+    pub spread_bps: f64, // Half of the spread is paid on every backtest fill
+    #[serde(default)]
+    pub volume_impact: f64, // Adverse slippage per unit of position / kline volume
+    #[serde(default)]
+    pub entry_order: EntryOrder,
+    #[serde(default)]
+    pub limit_offset_bps: f64, // Limit price this far from the close in favor of the entry
+    #[serde(default = "default_limit_timeout_secs")]
+    pub limit_timeout_secs: f64, // Post-only orders are canceled after it during live trade
+    #[serde(default = "default_limit_fallback_market")]
+    pub limit_fallback_market: bool, // Enter the unfilled rest of a limit entry at market
+    pub maker_fee_rate: Option<f64>, // fee_rate when omitted
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TpLevel {
+    pub r_multiple: f64,
+    pub fraction: f64, // Fraction of the initial position
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    #[default]
+    Both,
+    LongOnly,
+    ShortOnly,
+}
+
+impl Direction {
+    /// Entries only, the opposite signal still closes the open trades.
+    pub fn allows(&self, entry_side: &TradeSide) -> bool {
+        match self {
+            Direction::Both => true,
+            Direction::LongOnly => *entry_side == TradeSide::Buy,
+            Direction::ShortOnly => *entry_side == TradeSide::Sell,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Pyramiding {
+    #[default]
+    Unlimited,
+    Single,
+    Stack,
+    ScaleIn,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FillTiming {
+    #[default]
+    Close,
+    NextOpen,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryOrder {
+    #[default]
+    Market,
+    PostOnly,
+    Ioc,
+}
+
+fn default_regime_bars() -> usize {
+    7
+}
+
+fn default_regime_slope_bars() -> usize {
+    1
 }
 
+fn default_candle_filter() -> bool {
+    true
+}
+
+fn default_max_entries() -> usize {
+    1
+}
+
+fn default_scale_in_decay() -> f64 {
+    0.5
+}
+
+fn default_limit_timeout_secs() -> f64 {
+    60.
+}
+
+fn default_limit_fallback_market() -> bool {
+    true
+}
+
+fn default_secrets_path() -> String {
+    "./secrets.json".to_owned()
+}
+
+fn default_atr_period() -> usize {
+    14
+}
+
+/// `Trade` with the state needed by the stop management and the take profit levels.
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct SettingConfig {
-    pub from: String,
+pub struct ManagedTrade {
+    #[serde(flatten)]
+    pub trade: Trade,
+    pub initial_sl_price: Option<f64>, // None for trades logged before the stop management
+    #[serde(default)]
+    pub bars_held: usize,
+    pub initial_position: Option<f64>,
+    #[serde(default)]
+    pub tp_level_index: usize,
+    #[serde(default)]
+    pub realized_profit: f64,
+    #[serde(default)]
+    pub stop_order_missing: bool, // The mirrored stop is not on the exchange, exit on the close
+    #[serde(default)]
+    pub entry_fee: f64, // Fee paid on the entry of initial_position
+}
+
+impl ManagedTrade {
+    pub fn new(trade: Trade) -> ManagedTrade {
+        ManagedTrade {
+            initial_sl_price: Some(trade.sl_price),
+            initial_position: Some(trade.position),
+            trade,
+            bars_held: 0,
+            tp_level_index: 0,
+            realized_profit: 0.,
+            stop_order_missing: false,
+            entry_fee: 0.,
+        }
+    }
+
+    /// Price distance between the entry and the initial stop, i.e. 1R.
+    pub fn initial_risk(&self) -> f64 {
+        let initial_sl_price = self.initial_sl_price.unwrap_or(self.trade.sl_price);
+        (self.trade.entry_price - initial_sl_price).abs()
+    }
+
+    pub fn initial_position(&self) -> f64 {
+        self.initial_position.unwrap_or(self.trade.position)
+    }
+
+    /// Enters at the filled position and price, the stops keep their distance to the entry.
+    pub fn fill(&mut self, position: f64, entry_price: f64) {
+        let shift = entry_price - self.trade.entry_price;
+        self.trade.entry_price = entry_price;
+        self.trade.sl_price += shift;
+        self.trade.tp_price += shift;
+        self.initial_sl_price = self.initial_sl_price.map(|sl_price| sl_price + shift);
+        self.trade.position = position;
+        self.initial_position = Some(position);
+    }
+}
+
+impl Deref for ManagedTrade {
+    type Target = Trade;
+
+    fn deref(&self) -> &Trade {
+        &self.trade
+    }
+}
+
+impl DerefMut for ManagedTrade {
+    fn deref_mut(&mut self) -> &mut Trade {
+        &mut self.trade
+    }
+}
+
+/// Data range of backtest and hypertune, no secrets are needed offline.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BacktestSettingConfig {
+    pub from: String, // %Y-%m-%d %H:%M:%S
     pub to: String,
-    pub version: String,
     pub symbol: String,
     pub collection_postfix: String,
-    pub api_key: String,
-    pub secret_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LiveSettingConfig {
+    pub version: String,
+    pub symbol: String,
+    #[serde(default = "default_secrets_path")]
+    pub secrets_path: String, // Used when the credentials are not in the environment
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<Secret>, // Rejected, only to catch settings with plaintext credentials
+    #[serde(default, skip_serializing)]
+    pub secret_key: Option<Secret>,
+    #[serde(default)]
+    pub risk: RiskConfig,
 }
 
 #[derive(Parser, Debug)]
-#[command(arg_required_else_help = false)]
+#[command(
+    name = "momentum",
+    about = "Momentum strategy backtest, hypertune and live trade"
+)]
 pub struct Cli {
-    #[arg(short = 'b', required = false)]
-    pub backtest_config: Option<PathBuf>,
-    #[arg(short = 'm', long = "mode")]
-    pub mode: Mode,
-    #[arg(short = 't', required = false)]
-    pub hypertune_config: Option<PathBuf>,
-    #[arg(short = 's', required = false)]
-    pub setting_config: Option<PathBuf>,
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Backtest a config over the klines of the setting config
+    Backtest {
+        /// Backtest config
+        #[arg(short = 'b', long)]
+        backtest_config: PathBuf,
+        /// Backtest setting config with the kline range
+        #[arg(short = 's', long)]
+        setting_config: PathBuf,
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Backtest every config of the hypertune ranges into hypertune_output.csv
+    Hypertune {
+        /// Hypertune config, objects are {min, max, step} ranges or {values: [...]}
+        #[arg(short = 't', long)]
+        hypertune_config: PathBuf,
+        /// Backtest setting config with the kline range
+        #[arg(short = 's', long)]
+        setting_config: PathBuf,
+        /// Show the number of configs and the estimated runtime without running them
+        #[arg(long)]
+        dry_run: bool,
+        /// Results of the configs already backtested on the same klines and code
+        #[arg(long, default_value = CACHE_PATH)]
+        cache: PathBuf,
+        /// Backtest every config again without the cache
+        #[arg(long)]
+        no_cache: bool,
+        /// Tune over these symbols with the collection_postfix of the setting config instead of its symbol
+        #[arg(long, value_delimiter = ',', conflicts_with = "basket")]
+        symbols: Vec<String>,
+        /// Tune over BTCUSDT, XRPUSDT, DOGEUSDT, AVAXUSDT and MATICUSDT
+        #[arg(long)]
+        basket: bool,
+        /// Combined objective of the symbols
+        #[arg(long, value_enum, default_value_t)]
+        basket_objective: BasketObjective,
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Resample the trades of a backtest into confidence intervals of its outcome
+    MonteCarlo(MonteCarloArgs),
+    /// Trade a backtest config on the exchange
+    Live {
+        /// Backtest config to trade
+        #[arg(short = 'b', long)]
+        backtest_config: PathBuf,
+        /// Live setting config
+        #[arg(short = 's', long)]
+        setting_config: PathBuf,
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Trade a backtest config on the exchange klines without placing orders
+    Paper {
+        /// Backtest config to trade
+        #[arg(short = 'b', long)]
+        backtest_config: PathBuf,
+        /// Live setting config, no credentials are needed
+        #[arg(short = 's', long)]
+        setting_config: PathBuf,
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Download the klines of the setting config from the exchange into the local db
+    Sync {
+        /// Backtest setting config with the kline range
+        #[arg(short = 's', long)]
+        setting_config: PathBuf,
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Show the usd balance of the account
+    Account {
+        /// Live setting config
+        #[arg(short = 's', long)]
+        setting_config: PathBuf,
+        /// Keep showing it every 10 secs
+        #[arg(long)]
+        watch: bool,
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Place a market order
+    Order {
+        /// Live setting config
+        #[arg(short = 's', long)]
+        setting_config: PathBuf,
+        /// Symbol of the setting config when omitted
+        #[arg(long)]
+        symbol: Option<String>,
+        #[arg(long, value_enum)]
+        side: OrderSideArg,
+        #[arg(long)]
+        quantity: f64,
+        #[command(flatten)]
+        config_args: ConfigArgs,
+    },
+    /// Replay a live session journal and check every decision is reproduced
+    Replay {
+        /// Journal of the session, ./journal/{version}_{start_ts}.jsonl
+        #[arg(short = 'j', long)]
+        journal: PathBuf,
+    },
+    /// Reports of backtests and live sessions
+    Report {
+        #[command(subcommand)]
+        report: ReportCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReportCommand {
+    /// Self-contained html report of a backtest, with its charts and metrics
+    Backtest(BacktestReportArgs),
+    /// Rank the hypertune output, render its heatmaps and write the recommended config
+    Hypertune(HypertuneReportArgs),
+    /// Per-trade differences between the live trades and a backtest of the same range
+    Divergence(DivergenceArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    /// Profile of the configs with profiles
+    #[arg(long)]
+    pub profile: Option<String>,
+    /// Override a config field, setting.key=value for the setting config
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+pub enum OrderSideArg {
+    Buy,
+    Sell,
+}
+
+#[derive(Args, Debug)]
+pub struct BacktestReportArgs {
+    /// Backtest config
+    #[arg(short = 'b', long)]
+    pub backtest_config: PathBuf,
+    /// Backtest setting config with the kline range
+    #[arg(short = 's', long)]
+    pub setting_config: PathBuf,
+    /// Report html
+    #[arg(short = 'o', long, default_value = "backtest_report.html")]
+    pub output: PathBuf,
+    #[command(flatten)]
+    pub config_args: ConfigArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct HypertuneReportArgs {
+    /// Hypertune config of the output
+    #[arg(short = 't', long)]
+    pub hypertune_config: PathBuf,
+    /// Hypertune output csv
+    #[arg(short = 'i', long, default_value = "hypertune_output.csv")]
+    pub input: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub objective: Objective,
+    /// Configs in each ranking
+    #[arg(long, default_value_t = 10)]
+    pub top: usize,
+    /// Report html
+    #[arg(short = 'o', long, default_value = "hypertune_report.html")]
+    pub output: PathBuf,
+    /// Backtest config of the recommended run, for live trade
+    #[arg(short = 'r', long, default_value = "recommended_config.json")]
+    pub recommended: PathBuf,
+    #[command(flatten)]
+    pub config_args: ConfigArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct MonteCarloArgs {
+    /// Backtest config
+    #[arg(short = 'b', long)]
+    pub backtest_config: PathBuf,
+    /// Backtest setting config with the kline range
+    #[arg(short = 's', long)]
+    pub setting_config: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub resampling: Resampling,
+    /// Simulated paths
+    #[arg(long, default_value_t = 10000, value_parser = clap::value_parser!(u64).range(1..))]
+    pub simulations: u64,
+    /// Consecutive trades of a block, for block-bootstrap
+    #[arg(long, default_value_t = 5)]
+    pub block_size: usize,
+    /// Two-sided confidence of the intervals
+    #[arg(long, default_value_t = 0.95)]
+    pub confidence: f64,
+    /// Part of initial_captial lost that ruins a path
+    #[arg(long, default_value_t = 0.5)]
+    pub ruin_ratio: f64,
+    /// Seed of the paths, random when not given
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Summary json
+    #[arg(short = 'o', long, default_value = "monte_carlo_output.json")]
+    pub output: PathBuf,
+    #[command(flatten)]
+    pub config_args: ConfigArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DivergenceArgs {
+    /// Backtest config of the live trade
+    #[arg(short = 'b', long)]
+    pub backtest_config: PathBuf,
+    /// Backtest setting config of the live session range
+    #[arg(short = 's', long)]
+    pub setting_config: PathBuf,
+    /// Version of the live trade
+    #[arg(short = 'v', long)]
+    pub version: String,
+    /// Exchange fills, csv of timestamp,side,price,quantity,fee,realized_profit
+    #[arg(short = 'f', long)]
+    pub fills: Option<PathBuf>,
+    /// Fills this long after the decision belong to the trade
+    #[arg(short = 'w', long, default_value_t = 60)]
+    pub fill_window_mins: i64,
+    /// Report csv
+    #[arg(short = 'o', long, default_value = "divergence_report.csv")]
+    pub output: PathBuf,
+    #[command(flatten)]
+    pub config_args: ConfigArgs,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direction_allows_the_entries_of_its_side() {
+        for (direction, buy, sell) in [
+            (Direction::Both, true, true),
+            (Direction::LongOnly, true, false),
+            (Direction::ShortOnly, false, true),
+        ] {
+            assert_eq!(direction.allows(&TradeSide::Buy), buy);
+            assert_eq!(direction.allows(&TradeSide::Sell), sell);
+        }
+    }
 }
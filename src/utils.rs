@@ -4,15 +4,18 @@ use futures::TryStreamExt;
 use log::{info, warn};
 use mongodb::{
     bson::{self, doc, Document},
-    options::FindOptions,
+    options::{FindOptions, ReplaceOptions},
 };
 use serde_json::json;
 use trade_utils::{
-    clients::mongo_client::MongoClient,
-    types::{kline::Kline, trade::Trade},
+    clients::{binance::api::BinanceFuturesApiClient, mongo_client::MongoClient},
+    types::kline::Kline,
 };
 
-use crate::consts::{KLINE_DB, LOCAL_MONGO_CONNECTION_STRING};
+use crate::{
+    consts::{KLINE_DB, LOCAL_MONGO_CONNECTION_STRING},
+    types::ManagedTrade,
+};
 
 pub const LOG_DB: &str = "momentum_logs";
 pub const LOG_COLLECTION: &str = "trades";
@@ -20,8 +23,8 @@ pub const LOG_COLLECTION: &str = "trades";
 pub fn get_klines_from_db(from_str: &str, to_str: &str, collection: &str) -> Vec<Kline> {
     let from_datetime = NaiveDateTime::parse_from_str(from_str, "%Y-%m-%d %H:%M:%S").unwrap();
     let to_datetime = NaiveDateTime::parse_from_str(to_str, "%Y-%m-%d %H:%M:%S").unwrap();
-    let from_ts_ms = from_datetime.timestamp_millis();
-    let to_ts_ms = to_datetime.timestamp_millis();
+    let from_ts_ms = from_datetime.and_utc().timestamp_millis();
+    let to_ts_ms = to_datetime.and_utc().timestamp_millis();
 
     let mongo_clinet = task::block_on(MongoClient::new(LOCAL_MONGO_CONNECTION_STRING));
     let klines =
@@ -29,7 +32,7 @@ pub fn get_klines_from_db(from_str: &str, to_str: &str, collection: &str) -> Vec
     klines
 }
 
-pub fn log_trades(trades: &Vec<Trade>, version: &str) {
+pub fn log_trades(trades: &Vec<ManagedTrade>, version: &str) {
     let mongo_clinet = task::block_on(MongoClient::new(LOCAL_MONGO_CONNECTION_STRING));
     let collection = mongo_clinet
         .client
@@ -49,7 +52,7 @@ pub fn log_trades(trades: &Vec<Trade>, version: &str) {
     }
 }
 
-pub async fn get_trades(version: &str) -> Vec<Trade> {
+pub async fn get_trades(version: &str) -> Vec<ManagedTrade> {
     let mongo_clinet = MongoClient::new(LOCAL_MONGO_CONNECTION_STRING).await;
     let collection = mongo_clinet
         .client
@@ -62,8 +65,82 @@ pub async fn get_trades(version: &str) -> Vec<Trade> {
     let mut cursor = collection.find(filter, find_options).await.unwrap();
     while let Some(doc) = cursor.try_next().await.unwrap() {
         let trades_bson = doc.get("trades").unwrap().to_owned();
-        let trades: Vec<Trade> = bson::from_bson(trades_bson).unwrap();
+        let trades: Vec<ManagedTrade> = bson::from_bson(trades_bson).unwrap();
         return trades; // return the newest one
     }
     Vec::new()
 }
+
+/// Every logged snapshot of the trades of `version` as (timestamp in ms, trades), oldest first.
+pub async fn get_trade_snapshots(version: &str) -> Vec<(i64, Vec<ManagedTrade>)> {
+    let mongo_clinet = MongoClient::new(LOCAL_MONGO_CONNECTION_STRING).await;
+    let collection = mongo_clinet
+        .client
+        .database(LOG_DB)
+        .collection::<Document>(LOG_COLLECTION);
+    let filter = doc! { "version": version };
+    let find_options = FindOptions::builder().sort(doc! { "timestamp": 1 }).build();
+    let mut cursor = collection.find(filter, find_options).await.unwrap();
+    let mut snapshots = Vec::new();
+    while let Some(doc) = cursor.try_next().await.unwrap() {
+        let timestamp = match doc.get("timestamp").unwrap() {
+            bson::Bson::Int32(timestamp) => *timestamp as i64,
+            bson::Bson::Int64(timestamp) => *timestamp,
+            timestamp => timestamp.as_f64().unwrap() as i64,
+        } * 1000; // Logged in secs
+        let trades_bson = doc.get("trades").unwrap().to_owned();
+        let trades: Vec<ManagedTrade> = bson::from_bson(trades_bson).unwrap();
+        snapshots.push((timestamp, trades));
+    }
+    snapshots
+}
+
+/// Downloads the closed klines in [from_ts_ms, to_ts_ms] into `collection` of the kline db,
+/// klines already in the db are replaced. Returns the number of synced klines.
+pub fn sync_klines(
+    api_client: &BinanceFuturesApiClient,
+    symbol: &str,
+    interval: &str,
+    from_ts_ms: i64,
+    to_ts_ms: i64,
+    collection: &str,
+) -> usize {
+    let mongo_clinet = task::block_on(MongoClient::new(LOCAL_MONGO_CONNECTION_STRING));
+    let collection = mongo_clinet
+        .client
+        .database(KLINE_DB)
+        .collection::<Document>(collection);
+    let replace_options = ReplaceOptions::builder().upsert(true).build();
+    let now = Utc::now().timestamp_millis();
+    let mut start_ts = from_ts_ms;
+    let mut synced = 0;
+    while start_ts <= to_ts_ms {
+        let start_time = start_ts.to_string();
+        let end_time = to_ts_ms.to_string();
+        let klines = task::block_on(api_client.get_klines(
+            symbol,
+            interval,
+            Some(start_time.as_str()),
+            Some(end_time.as_str()),
+            Some("1500"),
+        ))
+        .unwrap();
+        let last_close_timestamp = match klines.last() {
+            Some(kline) => kline.close_timestamp,
+            None => break,
+        };
+        for kline in klines.iter().filter(|kline| kline.close_timestamp < now) {
+            let kline_doc = bson::to_document(kline).unwrap();
+            task::block_on(collection.replace_one(
+                doc! { "open_timestamp": kline.open_timestamp },
+                kline_doc,
+                replace_options.clone(),
+            ))
+            .unwrap();
+            synced += 1;
+        }
+        info!("Synced {} klines until {}", synced, last_close_timestamp);
+        start_ts = last_close_timestamp + 1;
+    }
+    synced
+}